@@ -0,0 +1,108 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use musk::big_exp_float::BigExpFloat;
+use musk::database::Database;
+use musk::kmer_index::{IndexKind, KmerIndex};
+use musk::sampling::Sampling;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use roaring::RoaringTreemap;
+
+const KMER_LEN: usize = 12;
+const NUM_FILES: usize = 16;
+const KMER_DENSITY: f64 = 0.3;
+const READ_LEN: usize = 150;
+const NUM_QUERIES: usize = 10_000;
+
+const INDEX_KINDS: [IndexKind; 2] = [IndexKind::HashMap, IndexKind::Dense];
+
+// A database where each file contains a random fraction (KMER_DENSITY) of the kmer space
+fn random_database(rng: &mut StdRng, index_kind: IndexKind) -> Database {
+    let kmer_space = 4_u64.pow(KMER_LEN as u32);
+    let bitmaps = (0..NUM_FILES)
+        .map(|_| {
+            RoaringTreemap::from_iter((0..kmer_space).filter(|_| rng.random_bool(KMER_DENSITY)))
+        })
+        .collect::<Vec<RoaringTreemap>>();
+    let files = (0..NUM_FILES).map(|i| format!("{}.fna", i)).collect();
+    let tax_ids = (0..NUM_FILES).collect();
+
+    // Not canonical so that every kmer in the space can be queried
+    Database::from(
+        bitmaps,
+        false,
+        files,
+        tax_ids,
+        KMER_LEN,
+        None,
+        Sampling::All,
+        index_kind,
+    )
+}
+
+fn bench_index_lookup(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(42);
+    let kmer_space = 4_u64.pow(KMER_LEN as u32);
+    let kmers = (0..kmer_space)
+        .filter(|_| rng.random_bool(KMER_DENSITY))
+        .collect::<Vec<u64>>();
+    let queries = (0..NUM_QUERIES)
+        .map(|_| rng.random_range(0..kmer_space))
+        .collect::<Vec<u64>>();
+
+    let mut group = c.benchmark_group("kmer_index_get");
+    for index_kind in INDEX_KINDS {
+        let kmer_index = KmerIndex::from_kmers(index_kind, KMER_LEN, kmers.iter().copied());
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{:?}", index_kind)),
+            &queries,
+            |b, queries| {
+                b.iter(|| {
+                    queries
+                        .iter()
+                        .filter(|kmer| kmer_index.get(black_box(**kmer)).is_some())
+                        .count()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_classify(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(42);
+    let reads = (0..100)
+        .map(|_| {
+            (0..READ_LEN)
+                .map(|_| b"ACGT"[rng.random_range(0..4)])
+                .collect::<Vec<u8>>()
+        })
+        .collect::<Vec<Vec<u8>>>();
+    let n_max = (READ_LEN - KMER_LEN + 1) as u64;
+
+    let mut group = c.benchmark_group("classify");
+    group.sample_size(20);
+    for index_kind in INDEX_KINDS {
+        let database = random_database(&mut StdRng::seed_from_u64(7), index_kind);
+        let lookup_table = database.compute_loookup_table(n_max);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{:?}", index_kind)),
+            &reads,
+            |b, reads| {
+                b.iter(|| {
+                    for read in reads {
+                        black_box(database.classify(
+                            read,
+                            BigExpFloat::from_f64(1e-30),
+                            n_max,
+                            &lookup_table,
+                            None,
+                        ));
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_index_lookup, bench_classify);
+criterion_main!(benches);
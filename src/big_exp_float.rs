@@ -2,6 +2,7 @@ use crate::decode::{decode_f32, decode_f64};
 use num_traits::{One, Zero};
 use serde::{Deserialize, Serialize};
 use std::f32::consts::LN_2;
+use std::f64::consts::LOG10_2;
 use std::ops::{Add, Div, Mul, MulAssign, Neg, Sub};
 
 const ONE: BigExpFloat = BigExpFloat { exp: 0, float: 1.0 };
@@ -79,9 +80,18 @@ impl BigExpFloat {
         *self * *self
     }
 
+    pub fn is_nan(&self) -> bool {
+        self.float.is_nan()
+    }
+
     pub fn as_f64(&self) -> f64 {
         self.float as f64 * 2.0_f64.powi(self.exp)
     }
+
+    // Useful for reporting values that are too small to be represented by an f64
+    pub fn log10(&self) -> f64 {
+        (self.float as f64).log10() + (self.exp as f64 * LOG10_2)
+    }
 }
 
 impl Mul for BigExpFloat {
@@ -1,10 +1,11 @@
+use bio::io::fastx::{EitherRecord, Kind};
 use bio::io::{fasta, fastq};
 use clap::Parser;
 use musk::io::create_output_file;
 use musk::tracing::start_musk_tracing_subscriber;
-use musk::utility::{get_fasta_iter_of_file, get_fastq_iter_of_file};
+use musk::utility::get_reads_iter_of_file;
 use std::path::Path;
-use tracing::info;
+use tracing::{info, warn};
 
 /// Creates a run length encoding database
 #[derive(Parser)]
@@ -12,7 +13,7 @@ use tracing::info;
 #[clap(author = "Trevor S. <trevor.schneggenburger@gmail.com>")]
 struct Args {
     #[arg(short, long, action)]
-    /// Specify this flag if the file is a fasta file
+    /// Deprecated and ignored, the format of the reads file is detected automatically
     fasta: bool,
 
     #[arg(short, long, default_value_t = 180)]
@@ -26,7 +27,8 @@ struct Args {
     output_location: String,
 
     #[arg()]
-    /// Fasta reads file to chop
+    /// FASTA/FASTQ reads file to chop (the format is detected automatically).
+    /// Use '-' to read from stdin.
     reads: String,
 }
 
@@ -39,34 +41,42 @@ fn main() {
     let output_loc_path = Path::new(&args.output_location);
     let chop_length = args.length;
     let reads_path = Path::new(&args.reads);
-
     if args.fasta {
-        let output_file = create_output_file(output_loc_path, "chopped.fasta");
-        let mut writer = fasta::Writer::new(output_file);
+        warn!("-f/--fasta is deprecated and ignored, the format of the reads file is detected automatically");
+    }
 
-        let mut fasta_reads_iter = get_fasta_iter_of_file(reads_path);
+    let mut reads_iter = get_reads_iter_of_file(reads_path);
 
-        while let Some(Ok(read)) = fasta_reads_iter.next() {
-            let seq = if read.seq().len() < chop_length {
-                read.seq()
-            } else {
-                &read.seq()[..chop_length]
-            };
-            writer.write(read.id(), read.desc(), seq).unwrap();
-        }
-    } else {
-        let output_file = create_output_file(output_loc_path, "chopped.fastq");
-        let mut writer = fastq::Writer::new(output_file);
+    // The output format is the same as the input format
+    match reads_iter
+        .kind()
+        .expect("could not determine the format of the reads file")
+    {
+        Kind::FASTA => {
+            let output_file = create_output_file(output_loc_path, "chopped.fasta");
+            let mut writer = fasta::Writer::new(output_file);
 
-        let mut fastq_reads_iter = get_fastq_iter_of_file(reads_path);
+            while let Some(Ok(EitherRecord::FASTA(read))) = reads_iter.next() {
+                let seq = if read.seq().len() < chop_length {
+                    read.seq()
+                } else {
+                    &read.seq()[..chop_length]
+                };
+                writer.write(read.id(), read.desc(), seq).unwrap();
+            }
+        }
+        Kind::FASTQ => {
+            let output_file = create_output_file(output_loc_path, "chopped.fastq");
+            let mut writer = fastq::Writer::new(output_file);
 
-        while let Some(Ok(read)) = fastq_reads_iter.next() {
-            let (seq, qual) = if read.seq().len() < chop_length {
-                (read.seq(), read.qual())
-            } else {
-                (&read.seq()[..args.length], &read.qual()[..chop_length])
-            };
-            writer.write(read.id(), read.desc(), seq, qual).unwrap();
+            while let Some(Ok(EitherRecord::FASTQ(read))) = reads_iter.next() {
+                let (seq, qual) = if read.seq().len() < chop_length {
+                    (read.seq(), read.qual())
+                } else {
+                    (&read.seq()[..chop_length], &read.qual()[..chop_length])
+                };
+                writer.write(read.id(), read.desc(), seq, qual).unwrap();
+            }
         }
     }
 
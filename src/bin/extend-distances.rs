@@ -1,16 +1,19 @@
 use clap::Parser;
 use indicatif::ParallelProgressIterator;
 use itertools::Itertools;
-use musk::io::{create_output_file, dump_data_to_file, load_data_from_file, load_string2taxid};
+use musk::checkpoint::{describe_file2taxid, WorkDirectory};
+use musk::distances::PairwiseDistances;
+use musk::io::{
+    create_output_file, dump_data_to_file, load_data_from_file, load_string2taxid, FileKind,
+};
+use musk::sampling::Sampling;
 use musk::tracing::start_musk_tracing_subscriber;
 use musk::utility::create_bitmap;
 use rayon::prelude::*;
-use roaring::RoaringBitmap;
+use roaring::RoaringTreemap;
 use std::path::Path;
 use tracing::info;
 
-const CANONICAL: bool = true;
-
 /// Computes the lower triangle of a pairwise distance matrix from the input sequences (or sequence groups)
 #[derive(Parser)]
 #[clap(version, about)]
@@ -20,6 +23,11 @@ struct Args {
     /// Length of k-mer to use in the database
     kmer_length: usize,
 
+    #[arg(long, action, verbatim_doc_comment)]
+    /// Use k-mers as they appear in the sequences rather than canonical k-mers.
+    /// By default, the smaller of a k-mer and its reverse complement is used.
+    non_canonical: bool,
+
     #[arg(short, long, default_value_t = std::env::current_dir().unwrap().to_str().unwrap().to_string())]
     /// Where to write the output
     /// If a file, '.musk.pd' is added
@@ -27,6 +35,12 @@ struct Args {
     /// Name means: musk, (p)airwise (d)istances
     output_location: String,
 
+    #[arg(long, verbatim_doc_comment)]
+    /// Directory to save the bitmap and distances of each group in while computing.
+    /// If the run is interrupted, running the same command again resumes from the saved results.
+    /// A 'musk_work' directory is created inside it and removed once the distances are written.
+    work_directory: Option<String>,
+
     #[arg()]
     /// The original pairwise distances file
     distances: String,
@@ -53,6 +67,7 @@ fn main() {
     let distances_path = Path::new(&args.distances);
     let new_file2taxid_path = Path::new(&args.new_file2taxid);
     let kmer_len = args.kmer_length;
+    let canonical = !args.non_canonical;
     let new_ref_dir_path = Path::new(&args.new_reference_directory);
     let old_ref_dir_path = Path::new(&args.old_reference_directory);
     let output_loc_path = Path::new(&args.output_location);
@@ -61,27 +76,57 @@ fn main() {
     let output_file = create_output_file(output_loc_path, "musk.pd");
 
     info!("loading pairwise distances at {}", args.distances);
-    let (old_distances, old_file2taxid) =
-        load_data_from_file::<(Vec<Vec<u32>>, Vec<(String, usize)>)>(distances_path);
+    let old_distances = load_data_from_file::<PairwiseDistances>(distances_path);
+    old_distances.check_settings(kmer_len, canonical);
+    let PairwiseDistances {
+        distances: old_distances,
+        file2taxid: old_file2taxid,
+        ..
+    } = old_distances;
     let old_file2taxid_len = old_file2taxid.len();
 
     info!("loading new file2taxid at {:?}", new_file2taxid_path);
     let new_file2taxid = load_string2taxid(new_file2taxid_path);
+    let work_dir = args.work_directory.as_ref().map(|work_directory| {
+        let settings = format!(
+            "extend-distances of {} with {}, k-mer length {}, canonical {}",
+            describe_file2taxid(&old_file2taxid),
+            describe_file2taxid(&new_file2taxid),
+            kmer_len,
+            canonical
+        );
+        WorkDirectory::open(Path::new(work_directory), &settings)
+    });
+    if let Some(work_dir) = &work_dir {
+        let num_saved = (old_file2taxid_len..old_file2taxid_len + new_file2taxid.len())
+            .filter(|index| work_dir.contains(&row_name(*index)))
+            .count();
+        info!(
+            "{} of {} distance rows were already computed",
+            num_saved,
+            new_file2taxid.len()
+        );
+    }
 
     info!("creating bitmaps for the old file2taxid...");
     let old_bitmaps = old_file2taxid
         .par_iter()
+        .enumerate()
         .progress()
-        .map(|(files, _taxid)| {
+        .map(|(index, (files, _taxid))| {
             // Split the files up if they are grouped
             let file_paths = files
                 .split("$")
                 .map(|file| old_ref_dir_path.join(file))
                 .collect_vec();
 
-            create_bitmap(file_paths, kmer_len, CANONICAL)
+            let create = || create_bitmap(file_paths, kmer_len, None, canonical, Sampling::All);
+            match &work_dir {
+                Some(work_dir) => work_dir.load_or_create(&bitmap_name(index), create),
+                None => create(),
+            }
         })
-        .collect::<Vec<RoaringBitmap>>();
+        .collect::<Vec<RoaringTreemap>>();
 
     info!(
         "{} groups need to be added, creating roaring bitmaps for new file2taxid...",
@@ -89,16 +134,24 @@ fn main() {
     );
     let new_bitmaps = new_file2taxid
         .par_iter()
+        .enumerate()
         .progress()
-        .map(|(files, _taxid)| {
+        .map(|(index, (files, _taxid))| {
             let file_paths = files
                 .split("$")
                 .map(|file| new_ref_dir_path.join(file))
                 .collect_vec();
 
-            create_bitmap(file_paths, kmer_len, CANONICAL)
+            // Saved after the old bitmaps, at their index in the extended distances
+            let create = || create_bitmap(file_paths, kmer_len, None, canonical, Sampling::All);
+            match &work_dir {
+                Some(work_dir) => {
+                    work_dir.load_or_create(&bitmap_name(old_file2taxid_len + index), create)
+                }
+                None => create(),
+            }
         })
-        .collect::<Vec<RoaringBitmap>>();
+        .collect::<Vec<RoaringTreemap>>();
 
     info!("filling out distance matrix...");
     let all_bitmaps = old_bitmaps
@@ -114,7 +167,7 @@ fn main() {
             if index_1 < old_file2taxid_len {
                 None
             } else {
-                Some(
+                let create = || {
                     all_bitmaps[..=index_1]
                         .iter()
                         .enumerate()
@@ -127,8 +180,12 @@ fn main() {
                                 (bitmap_1.len() + bitmap_2.len() - (2 * intersection_size)) as u32
                             }
                         })
-                        .collect::<Vec<u32>>(),
-                )
+                        .collect::<Vec<u32>>()
+                };
+                match &work_dir {
+                    Some(work_dir) => Some(work_dir.load_or_create(&row_name(index_1), create)),
+                    None => Some(create()),
+                }
             }
         })
         .collect::<Vec<Vec<u32>>>();
@@ -144,7 +201,28 @@ fn main() {
         .chain(new_distances.into_iter())
         .collect_vec();
 
-    dump_data_to_file(&(all_distances, all_file2taxid), output_file).unwrap();
+    let all_distances = PairwiseDistances {
+        kmer_len: Some(kmer_len),
+        canonical,
+        distances: all_distances,
+        file2taxid: all_file2taxid,
+        kmer_counts: Some(all_bitmaps.iter().map(|bitmap| bitmap.len()).collect()),
+    };
+    dump_data_to_file(&all_distances, FileKind::PairwiseDistances, output_file)
+        .expect("could not output distances to file");
+
+    if let Some(work_dir) = work_dir {
+        info!("removing work directory...");
+        work_dir.remove();
+    }
 
     info!("done!");
 }
+
+fn bitmap_name(index: usize) -> String {
+    format!("bitmap_{}", index)
+}
+
+fn row_name(index: usize) -> String {
+    format!("row_{}", index)
+}
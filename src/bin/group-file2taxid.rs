@@ -2,17 +2,16 @@ use clap::Parser;
 use indicatif::ParallelProgressIterator;
 use musk::group::connected_components;
 use musk::io::{create_output_file, load_string2taxid};
+use musk::sampling::Sampling;
 use musk::tracing::start_musk_tracing_subscriber;
 use musk::utility::create_bitmap;
 use rayon::prelude::*;
-use roaring::RoaringBitmap;
+use roaring::RoaringTreemap;
 use std::collections::HashMap;
 use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
 use tracing::{debug, info};
 
-const CANONICAL: bool = true;
-
 /// Groups an input file2taxid
 /// Files with the same taxid are compared and if they are similar enough, they are combined
 #[derive(Parser)]
@@ -23,6 +22,11 @@ struct Args {
     /// Length of k-mer to use in the database
     kmer_length: usize,
 
+    #[arg(long, action, verbatim_doc_comment)]
+    /// Use k-mers as they appear in the sequences rather than canonical k-mers.
+    /// By default, the smaller of a k-mer and its reverse complement is used.
+    non_canonical: bool,
+
     #[arg(short, long, default_value_t = 0.95)]
     /// The Jaccard similarity required to combine reference sequences
     minimum_similarity: f64,
@@ -51,6 +55,7 @@ fn main() {
     let args = Args::parse();
     let file2taxid_path = Path::new(&args.file2taxid);
     let kmer_len = args.kmer_length;
+    let canonical = !args.non_canonical;
     let output_loc_path = Path::new(&args.output_location);
     let ref_dir_path = Path::new(&args.reference_directory);
 
@@ -95,8 +100,8 @@ fn main() {
         let bitmaps = file_paths
             .into_par_iter()
             .progress()
-            .map(|file| create_bitmap(vec![file], kmer_len, CANONICAL))
-            .collect::<Vec<RoaringBitmap>>();
+            .map(|file| create_bitmap(vec![file], kmer_len, None, canonical, Sampling::All))
+            .collect::<Vec<RoaringTreemap>>();
 
         debug!("performing comparisons...");
 
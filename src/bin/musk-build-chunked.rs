@@ -0,0 +1,164 @@
+use clap::Parser;
+use indicatif::ParallelProgressIterator;
+use itertools::Itertools;
+use musk::chunked::ChunkedBuilder;
+use musk::io::{create_output_file, load_string2taxid};
+use musk::kmer_iter::SpacedSeed;
+use musk::sampling::Sampling;
+use musk::tracing::start_musk_tracing_subscriber;
+use musk::utility::create_bitmap;
+use rayon::prelude::*;
+use std::path::Path;
+use tracing::info;
+
+/// Creates a mapped musk database (.mdb) file from a file2taxid (.f2t) file without holding every
+/// file's k-mers in memory at once.
+/// The k-mers of each file are split by prefix into buckets on disk, which are then compressed a
+/// chunk at a time so that the memory used stays below a limit.
+/// Buckets that do not fit in the limit are split again on longer prefixes.
+/// For significant database size improvement, the file2taxid should be ordered (.o.f2t).
+#[derive(Parser)]
+#[clap(version, about)]
+#[clap(author = "Trevor S. <trevor.schneggenburger@gmail.com>")]
+struct Args {
+    #[arg(short, long, default_value_t = 14)]
+    /// Length of k-mer to use in the database
+    kmer_length: usize,
+
+    #[arg(long, action, verbatim_doc_comment)]
+    /// Use k-mers as they appear in the sequences rather than canonical k-mers.
+    /// By default, the smaller of a k-mer and its reverse complement is used.
+    non_canonical: bool,
+
+    #[arg(long, conflicts_with = "kmer_length", verbatim_doc_comment)]
+    /// A spaced seed mask (e.g. 1101101101101101) to extract k-mers with instead of contiguous k-mers.
+    /// Only the positions marked 1 are part of a k-mer, so the k-mer length is the number of 1s.
+    spaced_seed: Option<SpacedSeed>,
+
+    #[arg(
+        short = 'w',
+        long,
+        conflicts_with = "syncmer_length",
+        verbatim_doc_comment
+    )]
+    /// Only store the minimizer of every window of W consecutive k-mers.
+    /// Reduces the database size by about (W + 1) / 2 times.
+    minimizer_window: Option<usize>,
+
+    #[arg(short, long, verbatim_doc_comment)]
+    /// Only store open syncmers: k-mers whose smallest s-mer of length S is at the start.
+    /// Reduces the database size by about k - S + 1 times.
+    syncmer_length: Option<usize>,
+
+    #[arg(short, long, default_value_t = 16.0, verbatim_doc_comment)]
+    /// The (estimated) memory in GiB that compressing each chunk may use.
+    /// Only a single k-mer that does not fit on its own is compressed over the limit.
+    /// Reading the references also holds the k-mers of one file per thread.
+    memory_limit: f64,
+
+    #[arg(short = 'd', long, default_value_t = std::env::current_dir().unwrap().to_str().unwrap().to_string(), verbatim_doc_comment)]
+    /// Directory to write temporary files to (about the size of every file's k-mers).
+    /// A 'musk_chunks' directory is created inside it and removed when the database is written.
+    work_directory: String,
+
+    #[arg(short, long, default_value_t = std::env::current_dir().unwrap().to_str().unwrap().to_string(), verbatim_doc_comment)]
+    /// Where to write the database (.mdb) file.
+    /// If a file is provided, the extension '.musk.mdb' is added.
+    /// If a directory is provided, 'musk.mdb' will be the file name.
+    output_location: String,
+
+    #[arg()]
+    /// The file2taxid (.f2t) file. Preferrably ordered (.o.f2t).
+    file2taxid: String,
+
+    #[arg()]
+    /// Directory with FASTA files targets of the reference database
+    reference_directory: String,
+}
+
+fn main() {
+    // Initialize the tracing subscriber to handle debug, info, warn, and error macro calls
+    start_musk_tracing_subscriber();
+
+    // Parse arguments from the command line
+    let args = Args::parse();
+    let canonical = !args.non_canonical;
+    let kmer_len = match &args.spaced_seed {
+        Some(spaced_seed) => {
+            info!("using spaced seed {}", spaced_seed);
+            spaced_seed.weight()
+        }
+        None => args.kmer_length,
+    };
+    let sampling = match (args.minimizer_window, args.syncmer_length) {
+        (Some(window), _) => Sampling::Minimizer { window },
+        (None, Some(smer_len)) => Sampling::OpenSyncmer { smer_len },
+        (None, None) => Sampling::All,
+    };
+    sampling.validate(kmer_len);
+    let memory_limit = (args.memory_limit * (1_u64 << 30) as f64) as u64;
+    let file2taxid_path = Path::new(&args.file2taxid);
+    let output_loc_path = Path::new(&args.output_location);
+    let ref_dir_path = Path::new(&args.reference_directory);
+    let work_dir_path = Path::new(&args.work_directory).join("musk_chunks");
+
+    // Create the output file so it errors if an incorrect output file is provided before computation
+    let output_file = create_output_file(output_loc_path, "musk.mdb");
+
+    // Load the file2taxid ordering
+    info!("loading file2taxid at {}", args.file2taxid);
+    let file2taxid_ordering = load_string2taxid(file2taxid_path);
+    let tax_ids = file2taxid_ordering.iter().map(|x| x.1).collect_vec();
+    let files = file2taxid_ordering.into_iter().map(|x| x.0).collect_vec();
+
+    info!("creating buckets in {:?}", work_dir_path);
+    let builder = ChunkedBuilder::new(
+        files.len(),
+        kmer_len,
+        args.spaced_seed.clone(),
+        canonical,
+        sampling,
+        &work_dir_path,
+    )
+    .expect("could not create bucket files");
+
+    info!(
+        "splitting the k-mers of each group into buckets ({})...",
+        sampling
+    );
+    files
+        .par_iter()
+        .enumerate()
+        .progress()
+        .for_each(|(index, files)| {
+            // Split the files up if they are grouped
+            let file_paths = files
+                .split("$")
+                .map(|file| ref_dir_path.join(file))
+                .collect_vec();
+
+            let bitmap = create_bitmap(
+                file_paths,
+                kmer_len,
+                args.spaced_seed.as_ref(),
+                canonical,
+                sampling,
+            );
+            builder
+                .add_bitmap(index, &bitmap)
+                .expect("could not write to bucket files");
+        });
+
+    info!("constructing database...");
+    let chunk_memories = builder
+        .finish(files, tax_ids, memory_limit, output_file)
+        .expect("could not write mapped database");
+    info!(
+        "compressed in {} chunks, the largest was estimated to use {:.3} GiB",
+        chunk_memories.len(),
+        chunk_memories.iter().max().copied().unwrap_or(0) as f64 / (1_u64 << 30) as f64
+    );
+    std::fs::remove_dir(&work_dir_path).expect("could not remove the bucket directory");
+
+    info!("done!");
+}
@@ -1,6 +1,6 @@
 use clap::Parser;
 use musk::database::Database;
-use musk::io::{create_output_file, dump_data_to_file, load_data_from_file};
+use musk::io::{create_output_file, dump_data_to_file, load_data_from_file, FileKind};
 use musk::tracing::start_musk_tracing_subscriber;
 use std::path::Path;
 use tracing::info;
@@ -54,7 +54,8 @@ fn main() {
     database.lossy_compression(compression_level);
 
     info!("dumping to file...");
-    dump_data_to_file(&database, output_file).expect("could not output database to file");
+    dump_data_to_file(&database, FileKind::LossyDatabase, output_file)
+        .expect("could not output database to file");
 
     info!("done!");
 }
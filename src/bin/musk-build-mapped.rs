@@ -0,0 +1,47 @@
+use clap::Parser;
+use musk::database::Database;
+use musk::io::{create_output_file, load_data_from_file};
+use musk::tracing::start_musk_tracing_subscriber;
+use std::path::Path;
+use tracing::info;
+
+/// Creates a mapped musk database (.mdb) file from a musk database (.db/.cdb) file.
+/// Mapped databases are queried directly from the file (without deserialization), so multiple
+/// classification processes can share the same database through the page cache.
+#[derive(Parser)]
+#[clap(version, about)]
+#[clap(author = "Trevor S. <trevor.schneggenburger@gmail.com>")]
+struct Args {
+    #[arg(short, long, default_value_t = std::env::current_dir().unwrap().to_str().unwrap().to_string(), verbatim_doc_comment)]
+    /// Where to write the database (.mdb) file.
+    /// If a file is provided, the extension '.musk.mdb' is added.
+    /// If a directory is provided, 'musk.mdb' will be the file name.
+    output_location: String,
+
+    #[arg()]
+    /// The database (.db/.cdb) file
+    database: String,
+}
+
+fn main() {
+    // Initialize the tracing subscriber to handle debug, info, warn, and error macro calls
+    start_musk_tracing_subscriber();
+
+    // Parse arguments from the command line
+    let args = Args::parse();
+    let output_loc_path = Path::new(&args.output_location);
+    let database_path = Path::new(&args.database);
+
+    // Create the output file so it errors if an incorrect output file is provided before computation
+    let output_file = create_output_file(output_loc_path, "musk.mdb");
+
+    info!("loading database at {:?}", database_path);
+    let database = load_data_from_file::<Database>(database_path);
+
+    info!("dumping to file...");
+    database
+        .dump_mapped(output_file)
+        .expect("could not output mapped database to file");
+
+    info!("done!");
+}
@@ -1,13 +1,16 @@
 use clap::Parser;
 use indicatif::ParallelProgressIterator;
 use itertools::Itertools;
-use musk::consts::CANONICAL;
+use musk::checkpoint::{describe_file2taxid, WorkDirectory};
 use musk::database::Database;
-use musk::io::{create_output_file, dump_data_to_file, load_string2taxid};
+use musk::io::{create_output_file, dump_data_to_file, load_string2taxid, FileKind};
+use musk::kmer_index::{IndexKind, MAX_DENSE_KMER_LEN};
+use musk::kmer_iter::SpacedSeed;
+use musk::sampling::Sampling;
 use musk::tracing::start_musk_tracing_subscriber;
 use musk::utility::create_bitmap;
 use rayon::prelude::*;
-use roaring::RoaringBitmap;
+use roaring::RoaringTreemap;
 use std::path::Path;
 use tracing::info;
 
@@ -21,12 +24,49 @@ struct Args {
     /// Length of k-mer to use in the database
     kmer_length: usize,
 
+    #[arg(long, action, verbatim_doc_comment)]
+    /// Use k-mers as they appear in the sequences rather than canonical k-mers.
+    /// By default, the smaller of a k-mer and its reverse complement is used.
+    non_canonical: bool,
+
+    #[arg(short, long, value_enum, default_value_t = IndexKind::HashMap, verbatim_doc_comment)]
+    /// How k-mers are looked up in the database.
+    /// A dense index has one entry for all 4^k k-mers (4^k * 4 bytes), so it is only
+    /// recommended for k <= 14 where most of the k-mer space is in the database.
+    index: IndexKind,
+
+    #[arg(long, conflicts_with = "kmer_length", verbatim_doc_comment)]
+    /// A spaced seed mask (e.g. 1101101101101101) to extract k-mers with instead of contiguous k-mers.
+    /// Only the positions marked 1 are part of a k-mer, so the k-mer length is the number of 1s.
+    spaced_seed: Option<SpacedSeed>,
+
+    #[arg(
+        short = 'w',
+        long,
+        conflicts_with = "syncmer_length",
+        verbatim_doc_comment
+    )]
+    /// Only store the minimizer of every window of W consecutive k-mers.
+    /// Reduces the database size by about (W + 1) / 2 times.
+    minimizer_window: Option<usize>,
+
+    #[arg(short, long, verbatim_doc_comment)]
+    /// Only store open syncmers: k-mers whose smallest s-mer of length S is at the start.
+    /// Reduces the database size by about k - S + 1 times.
+    syncmer_length: Option<usize>,
+
     #[arg(short, long, default_value_t = std::env::current_dir().unwrap().to_str().unwrap().to_string(), verbatim_doc_comment)]
     /// Where to write the database (.db) file.
     /// If a file is provided, the extension '.musk.db' is added.
     /// If a directory is provided, 'musk.db' will be the file name.
     output_location: String,
 
+    #[arg(long, verbatim_doc_comment)]
+    /// Directory to save the bitmap of each group in while building.
+    /// If the build is interrupted, running the same command again resumes from the saved bitmaps.
+    /// A 'musk_work' directory is created inside it and removed once the database is written.
+    work_directory: Option<String>,
+
     #[arg()]
     /// The file2taxid (.f2t) file. Preferrably ordered (.o.f2t).
     file2taxid: String,
@@ -42,7 +82,26 @@ fn main() {
 
     // Parse arguments from the command line
     let args = Args::parse();
-    let kmer_len = args.kmer_length;
+    let canonical = !args.non_canonical;
+    let kmer_len = match &args.spaced_seed {
+        Some(spaced_seed) => {
+            info!("using spaced seed {}", spaced_seed);
+            spaced_seed.weight()
+        }
+        None => args.kmer_length,
+    };
+    if args.index == IndexKind::Dense && kmer_len > MAX_DENSE_KMER_LEN {
+        panic!(
+            "a dense index can only be used with a k-mer length of at most {}",
+            MAX_DENSE_KMER_LEN
+        );
+    }
+    let sampling = match (args.minimizer_window, args.syncmer_length) {
+        (Some(window), _) => Sampling::Minimizer { window },
+        (None, Some(smer_len)) => Sampling::OpenSyncmer { smer_len },
+        (None, None) => Sampling::All,
+    };
+    sampling.validate(kmer_len);
     let file2taxid_path = Path::new(&args.file2taxid);
     let output_loc_path = Path::new(&args.output_location);
     let ref_dir_path = Path::new(&args.reference_directory);
@@ -53,29 +112,85 @@ fn main() {
     // Load the file2taxid ordering
     info!("loading file2taxid at {}", args.file2taxid);
     let file2taxid_ordering = load_string2taxid(file2taxid_path);
+    let work_dir = args.work_directory.as_ref().map(|work_directory| {
+        let settings = format!(
+            "musk-build bitmaps of {}, k-mer length {}, canonical {}, spaced seed {:?}, {}",
+            describe_file2taxid(&file2taxid_ordering),
+            kmer_len,
+            canonical,
+            args.spaced_seed
+                .as_ref()
+                .map(|spaced_seed| spaced_seed.to_string()),
+            sampling
+        );
+        WorkDirectory::open(Path::new(work_directory), &settings)
+    });
     let tax_ids = file2taxid_ordering.iter().map(|x| x.1).collect_vec();
     let files = file2taxid_ordering.into_iter().map(|x| x.0).collect_vec();
 
-    info!("creating roaring bitmaps for each group...");
+    if let Some(work_dir) = &work_dir {
+        let num_saved = (0..files.len())
+            .filter(|index| work_dir.contains(&bitmap_name(*index)))
+            .count();
+        info!(
+            "{} of {} bitmaps were already created",
+            num_saved,
+            files.len()
+        );
+    }
+
+    info!("creating roaring bitmaps for each group ({})...", sampling);
     let bitmaps = files
         .par_iter()
+        .enumerate()
         .progress()
-        .map(|files| {
+        .map(|(index, files)| {
             // Split the files up if they are grouped
             let file_paths = files
                 .split("$")
                 .map(|file| ref_dir_path.join(file))
                 .collect_vec();
 
-            create_bitmap(file_paths, kmer_len, CANONICAL)
+            let create = || {
+                create_bitmap(
+                    file_paths,
+                    kmer_len,
+                    args.spaced_seed.as_ref(),
+                    canonical,
+                    sampling,
+                )
+            };
+            match &work_dir {
+                Some(work_dir) => work_dir.load_or_create(&bitmap_name(index), create),
+                None => create(),
+            }
         })
-        .collect::<Vec<RoaringBitmap>>();
+        .collect::<Vec<RoaringTreemap>>();
 
     info!("constructing database...");
-    let database = Database::from(bitmaps, CANONICAL, files, tax_ids, kmer_len);
+    let database = Database::from(
+        bitmaps,
+        canonical,
+        files,
+        tax_ids,
+        kmer_len,
+        args.spaced_seed,
+        sampling,
+        args.index,
+    );
 
     info!("dumping to file...");
-    dump_data_to_file(&database, output_file).expect("could not serialize database to file");
+    dump_data_to_file(&database, FileKind::Database, output_file)
+        .expect("could not serialize database to file");
+
+    if let Some(work_dir) = work_dir {
+        info!("removing work directory...");
+        work_dir.remove();
+    }
 
     info!("done!");
 }
+
+fn bitmap_name(index: usize) -> String {
+    format!("bitmap_{}", index)
+}
@@ -1,10 +1,15 @@
-use clap::Parser;
+use bio::io::fastx::{EitherRecord, Record};
+use clap::{Parser, ValueEnum};
+use itertools::{EitherOrBoth, Itertools};
 use musk::big_exp_float::BigExpFloat;
 use musk::database::Database;
-use musk::io::{create_output_file, load_data_from_file};
+use musk::io::create_output_file;
+use musk::lca::{check_tax_ids, load_taxonomy};
+use musk::report::{kraken_kmer_mapping, kraken_line, write_kreport};
 use musk::tracing::start_musk_tracing_subscriber;
-use musk::utility::get_fastq_iter_of_file;
+use musk::utility::get_reads_iter_of_file;
 use rayon::prelude::*;
+use std::collections::HashMap;
 use std::io::{BufWriter, Write};
 use std::ops::Neg;
 use std::path::Path;
@@ -12,8 +17,10 @@ use std::sync::Mutex;
 use std::time::Instant;
 use tracing::{debug, info, warn};
 
-/// Classifies the input reads using a musk database (.db/.cdb) file.
+/// Classifies the input reads using a musk database (.db/.cdb/.mdb) file.
 /// Output is a readid2file (.r2f) mapping, including the taxid for the file if it was provided during database construction.
+/// If a mates file is provided, reads are classified as pairs and one line is output per fragment.
+/// Kraken style output (.kraken) and reports (.kreport) can be created instead for use with downstream tools.
 #[derive(Parser)]
 #[clap(version, about)]
 #[clap(author = "Trevor S. <trevor.schneggenburger@gmail.com>")]
@@ -31,15 +38,65 @@ struct Args {
     /// Where to write the readid2file (.r2f) file.
     /// If a file is provided, the extension '.musk.r2f' is added.
     /// If a directory is provided, 'musk.r2f' will be the file name.
+    /// The same applies to the '.musk.kraken', '.musk.kreport', and '.musk.top.tsv' extensions.
     output_location: String,
 
-    #[arg()]
-    /// The database (.db/.cdb) file
+    #[arg(short = 'f', long, value_enum, default_value_t = OutputFormat::R2f, verbatim_doc_comment)]
+    /// The format of the per-read output.
+    /// Kraken output requires a taxonomy.
+    output_format: OutputFormat,
+
+    #[arg(short = 'n', long, verbatim_doc_comment)]
+    /// Also write the N most significant files for each read, ranked from lowest to highest probability.
+    /// If the output location is a file, the extention '.musk.top.tsv' is added to it.
+    /// If it is a directory, 'musk.top.tsv' will be the file name.
+    /// Each line is <read-id>\t<rank>\t<file>\t<taxid>\t<log10-probability>.
+    top_n: Option<usize>,
+
+    #[arg(long, action)]
+    /// Also write a Kraken style report (.kreport) of the clade counts. Requires a taxonomy.
+    kreport: bool,
+
+    #[arg(long, action)]
+    /// Include every tax id in the database in the report, even if no reads were assigned
+    report_zero_counts: bool,
+
+    #[arg(short, long, verbatim_doc_comment)]
+    /// Directory with the NCBI taxonomy dump (nodes.dmp and names.dmp).
+    /// If provided, reads with several significant files are assigned to the lowest common ancestor of their taxids.
+    taxonomy: Option<String>,
+
+    #[arg(verbatim_doc_comment)]
+    /// The database (.db/.cdb/.mdb) file.
+    /// Mapped databases (.mdb) are queried directly from the file without loading it into memory
     database: String,
 
     #[arg()]
-    /// FASTQ reads file to query
+    /// FASTA/FASTQ reads file to query (R1 if paired-end). Use '-' to read from stdin.
     reads: String,
+
+    #[arg()]
+    /// Optional FASTA/FASTQ file with the mates of the reads (R2) for paired-end classification, in the same order as the reads
+    mates: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum OutputFormat {
+    /// <read-id>\t<file>\t<taxid> (.r2f)
+    R2f,
+    /// The r2f columns followed by the log10 probability and number of hits of the best file,
+    /// the number of k-mers queried, and the runner-up file with its log10 probability (.r2f)
+    Extended,
+    /// Kraken standard output (.kraken)
+    Kraken,
+}
+
+// Removes the '/1' or '/2' suffix that some sequencers add to distinguish mates
+fn fragment_id(read_id: &str) -> &str {
+    read_id
+        .strip_suffix("/1")
+        .or_else(|| read_id.strip_suffix("/2"))
+        .unwrap_or(read_id)
 }
 
 fn main() {
@@ -53,63 +110,225 @@ fn main() {
     let output_loc_path = Path::new(&args.output_location);
     let reads_path = Path::new(&args.reads);
 
-    // Create the output file so it errors if an incorrect output file is provided before computation
-    let output_file = create_output_file(output_loc_path, "musk.r2f");
+    // Both the reads and mates cannot come from stdin
+    if args.reads == "-" && args.mates.as_deref() == Some("-") {
+        panic!("only one of the reads or mates can be read from stdin!")
+    }
+
+    // Kraken output and reports need a taxonomy to find the tax ids of k-mers and clades
+    if (args.output_format == OutputFormat::Kraken || args.kreport) && args.taxonomy.is_none() {
+        panic!("kraken output and reports require a taxonomy to be provided!")
+    }
+
+    // Create the output files so it errors if an incorrect output file is provided before computation
+    let output_file = match args.output_format {
+        OutputFormat::R2f | OutputFormat::Extended => {
+            create_output_file(output_loc_path, "musk.r2f")
+        }
+        OutputFormat::Kraken => create_output_file(output_loc_path, "musk.kraken"),
+    };
+    let top_n_writer = args.top_n.map(|_| {
+        Mutex::new(BufWriter::new(create_output_file(
+            output_loc_path,
+            "musk.top.tsv",
+        )))
+    });
+    let kreport_file = if args.kreport {
+        Some(create_output_file(output_loc_path, "musk.kreport"))
+    } else {
+        None
+    };
 
     // Create a mutex over a writer to allow multiple threads to write to the output file
     let output_writer = Mutex::new(BufWriter::new(output_file));
 
     let stats = Mutex::new((0, 0, 0.0, 0.0));
 
+    // The number of reads assigned to each tax id, and the number of unclassified reads, for the report
+    let tax_id_counts = Mutex::new((HashMap::new(), 0_u64));
+
     info!("loading database at {:?}", database_path);
-    let database = load_data_from_file::<Database>(database_path);
+    let database = Database::load(database_path);
+    // Reads must be queried with the same spaced seed the database was built with
+    if let Some(spaced_seed) = database.spaced_seed() {
+        info!("using the database's spaced seed {}", spaced_seed);
+    }
+
+    // Load the taxonomy, if one was provided
+    let taxonomy = args.taxonomy.as_ref().map(|taxonomy| {
+        let taxonomy_path = Path::new(taxonomy);
+        info!("loading taxonomy at {:?}", taxonomy_path);
+        let taxonomy = load_taxonomy(taxonomy_path);
+        check_tax_ids(&taxonomy, database.tax_ids());
+        taxonomy
+    });
 
     info!("computing lookup table...");
     let lookup_table = database.compute_loookup_table(args.max_queries);
 
     info!("classifying reads...");
-    let read_iter = get_fastq_iter_of_file(reads_path);
+    let read_iter = get_reads_iter_of_file(reads_path);
+
+    // Pair each read with its mate, if a mates file was provided
+    let fragment_iter: Box<dyn Iterator<Item = _> + Send> = match &args.mates {
+        None => Box::new(read_iter.map(|record_result| (record_result, None))),
+        Some(mates) => {
+            let mates_path = Path::new(mates);
+            info!("pairing reads with mates at {:?}", mates_path);
+            Box::new(
+                read_iter
+                    .zip_longest(get_reads_iter_of_file(mates_path))
+                    .map(|pair| match pair {
+                        EitherOrBoth::Both(record_result, mate_result) => {
+                            (record_result, Some(mate_result))
+                        }
+                        // The files are mismatched, so the classification would be incomplete
+                        _ => panic!(
+                            "the reads and mates files have a different number of records, they are not paired"
+                        ),
+                    }),
+            )
+        }
+    };
     let start_time = Instant::now();
 
-    read_iter
+    fragment_iter
         .par_bridge()
         .into_par_iter()
-        .for_each(|record_result| match record_result {
-            Err(_) => {
-                warn!("error encountered while reading fastq file");
+        .for_each(|fragment| match fragment {
+            (Err(_), _) | (_, Some(Err(_))) => {
+                warn!("error encountered while reading reads file");
                 warn!("skipping the read that caused the error")
             }
-            Ok(record) => {
-                let (classification, (hit_lookup_time, prob_calc_time)) = database.classify(
-                    record.seq(),
-                    cutoff_threshold,
-                    args.max_queries,
-                    &lookup_table,
-                );
+            (Ok(record), mate) => {
+                let mate: Option<EitherRecord> = mate.map(|mate_result| mate_result.unwrap());
+                let (classification, (hit_lookup_time, prob_calc_time)) = match &mate {
+                    None => database.classify(
+                        record.seq(),
+                        cutoff_threshold,
+                        args.max_queries,
+                        &lookup_table,
+                        taxonomy.as_ref(),
+                    ),
+                    Some(mate) => {
+                        if fragment_id(record.id()) != fragment_id(mate.id()) {
+                            warn!(
+                                "read id {} does not match mate id {}",
+                                record.id(),
+                                mate.id()
+                            );
+                        }
+                        database.classify_pair(
+                            record.seq(),
+                            mate.seq(),
+                            cutoff_threshold,
+                            args.max_queries,
+                            &lookup_table,
+                            taxonomy.as_ref(),
+                        )
+                    }
+                };
+                let read_id = match &mate {
+                    None => record.id(),
+                    Some(_) => fragment_id(record.id()),
+                };
 
                 {
                     let mut stats = stats.lock().unwrap();
 
                     stats.0 += 1;
-                    stats.1 += record.seq().len();
+                    stats.1 += record.seq().len() + mate.as_ref().map_or(0, |m| m.seq().len());
                     stats.2 += hit_lookup_time;
                     stats.3 += prob_calc_time;
                 }
 
-                // Write classification result to output file
-                let mut writer = output_writer.lock().unwrap();
-                match classification {
-                    Some((file, taxid)) => {
-                        writer
-                            .write(format!("{}\t{}\t{}\n", record.id(), file, taxid).as_bytes())
-                            .expect("could not write to output file");
+                if args.kreport {
+                    let mut tax_id_counts = tax_id_counts.lock().unwrap();
+                    match classification.tax_id {
+                        Some(taxid) => *tax_id_counts.0.entry(taxid).or_insert(0) += 1,
+                        None => tax_id_counts.1 += 1,
                     }
-                    None => {
-                        writer
-                            .write(format!("{}\tU\t0\n", record.id()).as_bytes())
-                            .expect("could not write to output file");
+                }
+
+                let output_line = match args.output_format {
+                    OutputFormat::R2f => match classification.assignment() {
+                        Some((file, taxid)) => format!("{}\t{}\t{}\n", read_id, file, taxid),
+                        None => format!("{}\tU\t0\n", read_id),
+                    },
+                    OutputFormat::Extended => {
+                        let assignment = match classification.assignment() {
+                            Some((file, taxid)) => format!("{}\t{}", file, taxid),
+                            None => "U\t0".to_string(),
+                        };
+                        let (log10_prob, num_hits) = match classification.best() {
+                            Some(best) => (best.probability.log10(), best.num_hits),
+                            None => (0.0, 0),
+                        };
+                        let runner_up = match classification.runner_up() {
+                            Some(runner_up) => {
+                                format!("{}\t{}", runner_up.file, runner_up.probability.log10())
+                            }
+                            None => "-\t0".to_string(),
+                        };
+                        format!(
+                            "{}\t{}\t{}\t{}\t{}\t{}\n",
+                            read_id,
+                            assignment,
+                            log10_prob,
+                            num_hits,
+                            classification.num_queries,
+                            runner_up
+                        )
+                    }
+                    OutputFormat::Kraken => {
+                        let taxonomy = taxonomy.as_ref().unwrap();
+                        let (lengths, kmer_mappings): (Vec<usize>, Vec<String>) =
+                            [Some(&record), mate.as_ref()]
+                                .into_iter()
+                                .flatten()
+                                .map(|read| {
+                                    (
+                                        read.seq().len(),
+                                        kraken_kmer_mapping(
+                                            &database.kmer_tax_ids(read.seq(), taxonomy),
+                                        ),
+                                    )
+                                })
+                                .unzip();
+                        kraken_line(classification.tax_id, read_id, &lengths, &kmer_mappings)
                     }
                 };
+
+                // Write the ranked files to the top n file
+                if let (Some(n), Some(top_n_writer)) = (args.top_n, &top_n_writer) {
+                    let top_n_lines = classification
+                        .top_n(n)
+                        .iter()
+                        .enumerate()
+                        .map(|(rank, candidate)| {
+                            format!(
+                                "{}\t{}\t{}\t{}\t{}\n",
+                                read_id,
+                                rank + 1,
+                                candidate.file,
+                                candidate.tax_id,
+                                candidate.probability.log10()
+                            )
+                        })
+                        .collect::<String>();
+                    top_n_writer
+                        .lock()
+                        .unwrap()
+                        .write_all(top_n_lines.as_bytes())
+                        .expect("could not write to top n file");
+                }
+
+                // Write classification result to output file
+                output_writer
+                    .lock()
+                    .unwrap()
+                    .write_all(output_line.as_bytes())
+                    .expect("could not write to output file");
             }
         });
 
@@ -142,5 +361,35 @@ fn main() {
         .flush()
         .expect("could not write to output file");
 
+    if let Some(top_n_writer) = top_n_writer {
+        top_n_writer
+            .into_inner()
+            .expect("could not reclaim top n writer at the end of execution")
+            .flush()
+            .expect("could not write to top n file");
+    }
+
+    if let Some(kreport_file) = kreport_file {
+        info!("writing report...");
+        let (tax_id_counts, unclassified) = tax_id_counts.into_inner().unwrap();
+        let zero_count_tax_ids = if args.report_zero_counts {
+            database.tax_ids()
+        } else {
+            &[]
+        };
+        let mut kreport_writer = BufWriter::new(kreport_file);
+        write_kreport(
+            &mut kreport_writer,
+            taxonomy.as_ref().unwrap(),
+            &tax_id_counts,
+            unclassified,
+            zero_count_tax_ids,
+        )
+        .expect("could not write to report file");
+        kreport_writer
+            .flush()
+            .expect("could not write to report file");
+    }
+
     info!("done!");
 }
@@ -0,0 +1,84 @@
+use clap::Parser;
+use musk::distances::{DistanceMeasure, PairwiseDistances, TextFormat};
+use musk::io::{create_output_file, load_data_from_file};
+use musk::tracing::start_musk_tracing_subscriber;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use tracing::info;
+
+/// Exports a pairwise distance (.pd) file to text, as a PHYLIP distance matrix or a TSV with one
+/// line for each pair of files. Distances can be normalized to jaccard or mash distances.
+/// Files are named by their line in the file2taxid the distances were computed from.
+#[derive(Parser)]
+#[clap(version, about)]
+#[clap(author = "Trevor S. <trevor.schneggenburger@gmail.com>")]
+struct Args {
+    #[arg(short, long, default_value_t = std::env::current_dir().unwrap().to_str().unwrap().to_string(), verbatim_doc_comment)]
+    /// Where to write the exported distances.
+    /// If a file is provided, the extention '.musk.phy' or '.musk.tsv' is added.
+    /// If a directory is provided, 'musk.phy' or 'musk.tsv' will be the file name.
+    output_location: String,
+
+    #[arg(short, long, value_enum, default_value_t = TextFormat::Tsv)]
+    /// The text format to export to
+    format: TextFormat,
+
+    #[arg(short, long, value_enum, default_value_t = DistanceMeasure::Hamming, verbatim_doc_comment)]
+    /// How the distance between two files is measured.
+    /// Jaccard and mash distances need the number of k-mers of each file, which distances
+    /// written before musk files had a header do not record.
+    measure: DistanceMeasure,
+
+    #[arg(short, long, action, verbatim_doc_comment)]
+    /// Also write the number of k-mers of each file, which musk-import-distances needs to import jaccard and mash distances.
+    /// If the output location is a file, the extention '.musk.counts.tsv' is added to it.
+    /// If it is a directory, 'musk.counts.tsv' will be the file name.
+    kmer_counts: bool,
+
+    #[arg()]
+    /// The pairwise distances (.pd) file
+    distances: String,
+}
+
+fn main() {
+    // Initialize the tracing subscriber to handle debug, info, warn, and error macro calls
+    start_musk_tracing_subscriber();
+
+    // Parse arguments from the command line
+    let args = Args::parse();
+    let distances_path = Path::new(&args.distances);
+    let output_loc_path = Path::new(&args.output_location);
+
+    // Create the output file so it errors if an incorrect output file is provided before computation
+    let mut output_writer = BufWriter::new(create_output_file(
+        output_loc_path,
+        &format!("musk.{}", args.format.extension()),
+    ));
+    let counts_writer = args
+        .kmer_counts
+        .then(|| BufWriter::new(create_output_file(output_loc_path, "musk.counts.tsv")));
+
+    info!("loading distances at {}", args.distances);
+    let distances = load_data_from_file::<PairwiseDistances>(distances_path);
+
+    info!(
+        "distances loaded! exporting {} files ({:?}, {:?})...",
+        distances.file2taxid.len(),
+        args.format,
+        args.measure
+    );
+    distances
+        .write_text(&mut output_writer, args.format, args.measure)
+        .and_then(|()| output_writer.flush())
+        .expect("could not write to output file");
+
+    if let Some(mut counts_writer) = counts_writer {
+        info!("exporting number of k-mers of each file...");
+        distances
+            .write_kmer_counts(&mut counts_writer)
+            .and_then(|()| counts_writer.flush())
+            .expect("could not write to number of k-mers file");
+    }
+
+    info!("done!");
+}
@@ -0,0 +1,107 @@
+use clap::Parser;
+use musk::distances::{DistanceMeasure, PairwiseDistances, TextFormat};
+use musk::io::{create_output_file, dump_data_to_file, load_string2taxid, FileKind};
+use musk::tracing::start_musk_tracing_subscriber;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use tracing::{info, warn};
+
+/// Imports distances from text (see musk-export-distances) into a pairwise distance (.pd) file.
+/// The files are named by their line in the file2taxid, which also decides their order.
+/// Jaccard and mash distances are converted back to (approximate) hamming distances.
+#[derive(Parser)]
+#[clap(version, about)]
+#[clap(author = "Trevor S. <trevor.schneggenburger@gmail.com>")]
+struct Args {
+    #[arg(short, long, default_value_t = std::env::current_dir().unwrap().to_str().unwrap().to_string(), verbatim_doc_comment)]
+    /// Where to write the pairwise distance (.pd) file.
+    /// If a file is provided, the extention '.musk.pd' is added.
+    /// If a directory is provided, 'musk.pd' will be the file name.
+    output_location: String,
+
+    #[arg(short, long, value_enum, default_value_t = TextFormat::Tsv)]
+    /// The text format to import from
+    format: TextFormat,
+
+    #[arg(short, long, value_enum, default_value_t = DistanceMeasure::Hamming)]
+    /// How the distances being imported were measured
+    measure: DistanceMeasure,
+
+    #[arg(short = 'c', long, verbatim_doc_comment)]
+    /// The number of k-mers of each file (see musk-export-distances --kmer-counts), which is kept in the distances.
+    /// Needed to import jaccard and mash distances.
+    kmer_counts: Option<String>,
+
+    #[arg(short, long)]
+    /// Length of k-mer the distances were computed with, if known
+    kmer_length: Option<usize>,
+
+    #[arg(long, action)]
+    /// The distances were computed with non-canonical k-mers
+    non_canonical: bool,
+
+    #[arg()]
+    /// The file2taxid (.f2t) file of the files in the distances
+    file2taxid: String,
+
+    #[arg()]
+    /// The text distances file
+    distances: String,
+}
+
+fn main() {
+    // Initialize the tracing subscriber to handle debug, info, warn, and error macro calls
+    start_musk_tracing_subscriber();
+
+    // Parse arguments from the command line
+    let args = Args::parse();
+    let file2taxid_path = Path::new(&args.file2taxid);
+    let distances_path = Path::new(&args.distances);
+    let output_loc_path = Path::new(&args.output_location);
+
+    // Create the output file so it errors if an incorrect output file is provided before computation
+    let output_file = create_output_file(output_loc_path, "musk.pd");
+
+    info!("loading file2taxid at {}", args.file2taxid);
+    let file2taxid = load_string2taxid(file2taxid_path);
+    if args.kmer_length.is_none() {
+        warn!("no k-mer length was given, it will not be recorded in the distances");
+    }
+    let kmer_counts = args.kmer_counts.as_ref().map(|kmer_counts_path| {
+        info!(
+            "loading number of k-mers of each file at {}",
+            kmer_counts_path
+        );
+        let reader = BufReader::new(
+            File::open(kmer_counts_path).expect("could not open number of k-mers file"),
+        );
+        PairwiseDistances::read_kmer_counts(reader, &file2taxid)
+            .unwrap_or_else(|e| panic!("could not import number of k-mers: {}", e))
+    });
+
+    info!(
+        "reading distances at {} ({:?}, {:?})...",
+        args.distances, args.format, args.measure
+    );
+    let reader = BufReader::new(File::open(distances_path).expect("could not open distances file"));
+    let distances = PairwiseDistances::read_text(
+        reader,
+        args.format,
+        args.measure,
+        file2taxid,
+        kmer_counts,
+        args.kmer_length,
+        !args.non_canonical,
+    )
+    .unwrap_or_else(|e| panic!("could not import distances: {}", e));
+
+    info!(
+        "distances between {} files imported! outputting to file...",
+        distances.file2taxid.len()
+    );
+    dump_data_to_file(&distances, FileKind::PairwiseDistances, output_file)
+        .expect("could not output distances to file");
+
+    info!("done!");
+}
@@ -0,0 +1,108 @@
+use clap::Parser;
+use musk::database::Database;
+use musk::kmer_index::IndexKind;
+use musk::rle::Block;
+use musk::tracing::start_musk_tracing_subscriber;
+use rayon::prelude::*;
+use std::path::Path;
+use tracing::info;
+
+/// Prints information about a musk database (.db/.cdb/.mdb) file.
+/// This includes the k-mer settings, the number of k-mers and blocks, and the files in the database.
+#[derive(Parser)]
+#[clap(version, about)]
+#[clap(author = "Trevor S. <trevor.schneggenburger@gmail.com>")]
+struct Args {
+    #[arg(short, long, action)]
+    /// Only print the summary, not the information for each file
+    summary: bool,
+
+    #[arg()]
+    /// The database (.db/.cdb/.mdb) file
+    database: String,
+}
+
+fn main() {
+    // Initialize the tracing subscriber to handle debug, info, warn, and error macro calls
+    start_musk_tracing_subscriber();
+
+    // Parse arguments from the command line
+    let args = Args::parse();
+    let database_path = Path::new(&args.database);
+
+    info!("loading database at {:?}", database_path);
+    let database = Database::load(database_path);
+
+    // Count each type of block in the database
+    let (zeros, ones, uncompressed) = (0..database.num_kmers())
+        .into_par_iter()
+        .map(|position| {
+            database.rle_blocks(position).iter().fold(
+                (0_usize, 0_usize, 0_usize),
+                |(zeros, ones, uncompressed), block| match Block::from_u16(*block) {
+                    Block::Zeros(_) => (zeros + 1, ones, uncompressed),
+                    Block::Ones(_) => (zeros, ones + 1, uncompressed),
+                    Block::Uncompressed(_) => (zeros, ones, uncompressed + 1),
+                },
+            )
+        })
+        .reduce(
+            || (0, 0, 0),
+            |(zeros_1, ones_1, uncompressed_1), (zeros_2, ones_2, uncompressed_2)| {
+                (
+                    zeros_1 + zeros_2,
+                    ones_1 + ones_2,
+                    uncompressed_1 + uncompressed_2,
+                )
+            },
+        );
+    let total_blocks = zeros + ones + uncompressed;
+
+    // Avoid dividing by zero for an empty database
+    let percent = |count: usize| {
+        if total_blocks == 0 {
+            0.0
+        } else {
+            count as f64 * 100.0 / total_blocks as f64
+        }
+    };
+
+    println!("k-mer length:\t{}", database.kmer_len());
+    println!("canonical:\t{}", database.canonical());
+    match database.spaced_seed() {
+        Some(spaced_seed) => println!("spaced seed:\t{}", spaced_seed),
+        None => println!("spaced seed:\tnone"),
+    }
+    println!("sampling:\t{}", database.sampling());
+    match database.index_kind() {
+        Some(IndexKind::HashMap) => println!("k-mer index:\thash map"),
+        Some(IndexKind::Dense) => println!("k-mer index:\tdense"),
+        None => println!("k-mer index:\tmapped"),
+    }
+    println!("files:\t{}", database.num_files());
+    println!("distinct k-mers:\t{}", database.num_kmers());
+    println!("total blocks:\t{}", total_blocks);
+    println!("zeros blocks:\t{}\t({:.2}%)", zeros, percent(zeros));
+    println!("ones blocks:\t{}\t({:.2}%)", ones, percent(ones));
+    println!(
+        "uncompressed blocks:\t{}\t({:.2}%)",
+        uncompressed,
+        percent(uncompressed)
+    );
+
+    if !args.summary {
+        println!();
+        println!("index\tfile\ttaxid\tp");
+        for (index, ((file, tax_id), p)) in database
+            .files()
+            .iter()
+            .zip(database.tax_ids())
+            .zip(database.p_values())
+            .enumerate()
+        {
+            println!("{}\t{}\t{}\t{}", index, file, tax_id, p);
+        }
+    }
+
+    info!("done!");
+}
@@ -0,0 +1,103 @@
+use clap::Parser;
+use itertools::Itertools;
+use musk::database::Database;
+use musk::io::{create_separate_output_file, dump_data_to_file, load_data_from_file, FileKind};
+use musk::tracing::start_musk_tracing_subscriber;
+use std::collections::HashSet;
+use std::path::Path;
+use tracing::{info, warn};
+
+/// Merges musk databases (.db/.cdb/.mdb) built with the same k-mer settings into one database.
+/// The files of each database are appended in the order the databases are given.
+/// Lossy (.cdb) and lossless (.db) databases cannot be merged together, the merged database is the
+/// same kind as the inputs.
+#[derive(Parser)]
+#[clap(version, about)]
+#[clap(author = "Trevor S. <trevor.schneggenburger@gmail.com>")]
+struct Args {
+    #[arg(short, long, default_value_t = std::env::current_dir().unwrap().to_str().unwrap().to_string(), verbatim_doc_comment)]
+    /// Where to write the merged database file, which is the same kind as the first database.
+    /// If a file is provided, the extension '.musk.db' (or '.musk.cdb' for lossy databases) is added.
+    /// If a directory is provided, 'musk.db' (or 'musk.cdb') will be the file name.
+    output_location: String,
+
+    #[arg(required = true, num_args = 2.., verbatim_doc_comment)]
+    /// The databases to merge.
+    /// The first database must not be a mapped database (.mdb).
+    /// Mapped databases do not record whether they are lossy, they are merged as the kind of the first database.
+    databases: Vec<String>,
+}
+
+fn main() {
+    // Initialize the tracing subscriber to handle debug, info, warn, and error macro calls
+    start_musk_tracing_subscriber();
+
+    // Parse arguments from the command line
+    let args = Args::parse();
+    let output_loc_path = Path::new(&args.output_location);
+
+    // The merged database is the kind of the first database, which every other database must match
+    let first_path = Path::new(&args.databases[0]);
+    let kind = Database::file_kind(first_path);
+    if !matches!(kind, FileKind::Database | FileKind::LossyDatabase) {
+        panic!(
+            "{:?} is a {}, the first database must be a database (.db/.cdb)",
+            first_path, kind
+        );
+    }
+    for database_path in args.databases[1..].iter().map(Path::new) {
+        match Database::file_kind(database_path) {
+            other_kind if other_kind == kind => {}
+            FileKind::MappedDatabase => warn!(
+                "{:?} is a mapped database, which does not record whether it is lossy, merging it as a {}",
+                database_path, kind
+            ),
+            other_kind @ (FileKind::Database | FileKind::LossyDatabase) => panic!(
+                "{:?} is a {} but {:?} is a {}, lossy and lossless databases cannot be merged",
+                database_path, other_kind, first_path, kind
+            ),
+            other_kind => panic!("{:?} is a {}, not a database", database_path, other_kind),
+        }
+    }
+
+    // Create the output file so it errors if an incorrect output file is provided before computation,
+    // but never over one of the databases, which are not loaded yet
+    let database_paths = args.databases.iter().map(Path::new).collect_vec();
+    let output_file = create_separate_output_file(
+        output_loc_path,
+        &format!("musk.{}", kind.extension()),
+        &database_paths,
+    );
+
+    // The first database is loaded into memory and the others are merged into it
+    info!("loading database at {:?}", first_path);
+    let mut database = load_data_from_file::<Database>(first_path);
+
+    for database_path in args.databases[1..].iter().map(Path::new) {
+        info!("loading database at {:?}", database_path);
+        let other = Database::load(database_path);
+
+        let merged_files = database.files().iter().collect::<HashSet<&String>>();
+        let duplicate_files = other
+            .files()
+            .iter()
+            .filter(|file| merged_files.contains(file))
+            .collect_vec();
+        if !duplicate_files.is_empty() {
+            warn!(
+                "{} files in {:?} are already in the merged database (e.g. {}), they will be included twice",
+                duplicate_files.len(),
+                database_path,
+                duplicate_files[0]
+            );
+        }
+
+        info!("merging {} files...", other.num_files());
+        database.merge(&other);
+    }
+
+    info!("dumping to file...");
+    dump_data_to_file(&database, kind, output_file).expect("could not serialize database to file");
+
+    info!("done!");
+}
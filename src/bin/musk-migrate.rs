@@ -0,0 +1,104 @@
+use clap::Parser;
+use musk::database::Database;
+use musk::distances::{NeighborDistances, PairwiseDistances};
+use musk::io::{
+    create_separate_output_file, dump_data_to_file, load_data_from_file, read_file_header, FileKind,
+};
+use musk::size_estimate::KmerSample;
+use musk::tracing::start_musk_tracing_subscriber;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use tracing::info;
+
+/// Rewrites a musk file (.db/.cdb/.mdb/.pd) using the current file format.
+/// Files written by older versions of musk (including those without a header) are supported.
+#[derive(Parser)]
+#[clap(version, about)]
+#[clap(author = "Trevor S. <trevor.schneggenburger@gmail.com>")]
+struct Args {
+    #[arg(short, long, default_value_t = std::env::current_dir().unwrap().to_str().unwrap().to_string(), verbatim_doc_comment)]
+    /// Where to write the migrated file.
+    /// If a file is provided, the extension '.musk.<db/cdb/mdb/pd>' is added.
+    /// If a directory is provided, 'musk.<db/cdb/mdb/pd>' will be the file name.
+    output_location: String,
+
+    #[arg()]
+    /// The file to migrate.
+    /// Files without a header must have the extension '.db', '.cdb', or '.pd'.
+    file: String,
+}
+
+fn main() {
+    // Initialize the tracing subscriber to handle debug, info, warn, and error macro calls
+    start_musk_tracing_subscriber();
+
+    // Parse arguments from the command line
+    let args = Args::parse();
+    let output_loc_path = Path::new(&args.output_location);
+    let file_path = Path::new(&args.file);
+
+    // Use the header to determine the kind of file, falling back to the extension for old files
+    let mut buf_reader =
+        BufReader::new(File::open(file_path).expect("could not open file to migrate"));
+    let kind = match read_file_header(&mut buf_reader)
+        .unwrap_or_else(|e| panic!("could not read header of {:?}: {}", file_path, e))
+    {
+        Some(header) => {
+            info!(
+                "{:?} is a {} written with file format version {}",
+                file_path, header.kind, header.version
+            );
+            header.kind
+        }
+        None => FileKind::from_path(file_path).unwrap_or_else(|| {
+            panic!(
+                "{:?} does not have a header and its extension is not one of '.db', '.cdb', or '.pd'",
+                file_path
+            )
+        }),
+    };
+    drop(buf_reader);
+
+    // Create the output file so it errors if an incorrect output file is provided before computation,
+    // but never over the file to migrate, which is not loaded yet
+    let output_file = create_separate_output_file(
+        output_loc_path,
+        &format!("musk.{}", kind.extension()),
+        &[file_path],
+    );
+
+    info!("loading {} at {:?}", kind, file_path);
+    let result = match kind {
+        FileKind::Database | FileKind::LossyDatabase => {
+            let database = load_data_from_file::<Database>(file_path);
+            info!("dumping to file...");
+            dump_data_to_file(&database, kind, output_file)
+        }
+        FileKind::MappedDatabase => {
+            let database = Database::load(file_path);
+            info!("dumping to file...");
+            database
+                .dump_mapped(output_file)
+                .map_err(bincode::Error::from)
+        }
+        FileKind::PairwiseDistances => {
+            let distances = load_data_from_file::<PairwiseDistances>(file_path);
+            info!("dumping to file...");
+            dump_data_to_file(&distances, kind, output_file)
+        }
+        FileKind::NeighborDistances => {
+            let distances = load_data_from_file::<NeighborDistances>(file_path);
+            info!("dumping to file...");
+            dump_data_to_file(&distances, kind, output_file)
+        }
+        FileKind::KmerSample => {
+            let kmer_sample = load_data_from_file::<KmerSample>(file_path);
+            info!("dumping to file...");
+            dump_data_to_file(&kmer_sample, kind, output_file)
+        }
+    };
+    result.expect("could not output migrated data to file");
+
+    info!("done!");
+}
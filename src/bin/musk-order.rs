@@ -1,16 +1,23 @@
 use clap::Parser;
 use musk::{
-    io::{create_output_file, load_data_from_file},
-    order::{greedy_ordering, ordering_statistics},
+    distances::{NeighborDistances, PairwiseDistances},
+    io::{create_output_file, load_data_from_file, read_file_header, FileKind},
+    order::{
+        greedy_ordering, mst_ordering, multi_start_greedy_ordering, or_opt, ordering_statistics,
+        two_opt, FileDistances, OrderingAlgorithm,
+    },
+    size_estimate::KmerSample,
     tracing::start_musk_tracing_subscriber,
 };
 use std::{
-    io::{BufWriter, Write},
+    fs::File,
+    io::{BufReader, BufWriter, Write},
     path::Path,
 };
-use tracing::{debug, info};
+use tracing::info;
 
-/// Creates an ordered file2taxid (.o.f2t) file based on a pairwise distance matrix.
+/// Creates an ordered file2taxid (.o.f2t) file based on a pairwise distance matrix
+/// (or the distances from each file to its nearest neighbors).
 /// This is done such that the total hamming distance of the ordering is as small as possible.
 #[derive(Parser)]
 #[clap(version, about)]
@@ -22,12 +29,36 @@ struct Args {
     /// If a directory is provided, 'musk.o.f2t' will be the file name.
     output_location: String,
 
+    #[arg(short, long, value_enum, default_value_t = OrderingAlgorithm::Greedy)]
+    /// How the ordering is constructed
+    algorithm: OrderingAlgorithm,
+
     #[arg(short, long, default_value_t = 0)]
-    /// Start index of the naive shortest path traversal
+    /// Start index of the greedy and minimum spanning tree orderings
     start: usize,
 
+    #[arg(short = 'n', long, default_value_t = 16)]
+    /// The number of start files tried by the multi start greedy ordering
+    num_starts: usize,
+
+    #[arg(short, long, action, verbatim_doc_comment)]
+    /// Improve the ordering with local search (2-opt and Or-opt) until it can not be shortened.
+    /// Files are only moved next to their neighbors, so each pass is quadratic in the number of files
+    /// with pairwise distances (.pd) but linear with nearest neighbor distances (.knn).
+    local_search: bool,
+
+    #[arg(short, long, verbatim_doc_comment)]
+    /// A k-mer sample (.ks) of the same file2taxid (see musk-pairwise-distances --sample-fraction).
+    /// If provided, the ordering is improved further by moving files next to their nearest
+    /// neighbors when it lowers the estimated number of run length encoding blocks.
+    kmer_sample: Option<String>,
+
+    #[arg(long, default_value_t = 8)]
+    /// The number of nearest neighbors each file may be moved next to when using a k-mer sample
+    neighbors: usize,
+
     #[arg()]
-    /// The pairwise distances (.pd) file
+    /// The pairwise distances (.pd) or nearest neighbor distances (.knn) file
     distances: String,
 }
 
@@ -44,17 +75,23 @@ fn main() {
     let mut output_writer = BufWriter::new(create_output_file(output_loc_path, "musk.o.f2t"));
 
     info!("loading distances at {}", args.distances);
-    let (distances, file2taxid) =
-        load_data_from_file::<(Vec<Vec<u32>>, Vec<(String, usize)>)>(distances_file);
-
-    info!("distances loaded! finding ordering...");
-    // Perform the greedy solution -- no other options for right now
-    let greedy_ordering = greedy_ordering(&distances, args.start);
-    let (avg_dist, total_dist) = ordering_statistics(&greedy_ordering, &distances);
-    debug!("length of tour: {}", total_dist);
-    debug!("average distance between files: {}", avg_dist);
+    let (ordering, file2taxid) = match distances_kind(distances_file) {
+        FileKind::NeighborDistances => {
+            let distances = load_data_from_file::<NeighborDistances>(distances_file);
+            let ordering = find_ordering(&args, &distances, &distances.file2taxid);
+            (ordering, distances.file2taxid)
+        }
+        _ => {
+            let PairwiseDistances {
+                distances,
+                file2taxid,
+                ..
+            } = load_data_from_file::<PairwiseDistances>(distances_file);
+            (find_ordering(&args, &distances, &file2taxid), file2taxid)
+        }
+    };
 
-    for index in greedy_ordering {
+    for index in ordering {
         let (files_string, taxid) = &file2taxid[index];
         output_writer
             .write(format!("{}\t{}\n", *files_string, *taxid).as_bytes())
@@ -65,3 +102,81 @@ fn main() {
 
     info!("done!");
 }
+
+// The kind of the distances file, files without a header are pairwise distances
+fn distances_kind(path: &Path) -> FileKind {
+    let mut buf_reader = BufReader::new(File::open(path).expect("could not open distances file"));
+    match read_file_header(&mut buf_reader)
+        .unwrap_or_else(|e| panic!("could not read header of {:?}: {}", path, e))
+    {
+        Some(header) => header.kind,
+        None => FileKind::PairwiseDistances,
+    }
+}
+
+fn find_ordering<D: FileDistances>(
+    args: &Args,
+    distances: &D,
+    file2taxid: &[(String, usize)],
+) -> Vec<usize> {
+    info!(
+        "distances loaded! finding ordering ({:?})...",
+        args.algorithm
+    );
+    let mut ordering = match args.algorithm {
+        OrderingAlgorithm::Greedy => greedy_ordering(distances, args.start),
+        OrderingAlgorithm::MultiStartGreedy => {
+            multi_start_greedy_ordering(distances, args.num_starts)
+        }
+        OrderingAlgorithm::Mst => mst_ordering(distances, args.start),
+    };
+    log_statistics(&format!("{:?}", args.algorithm), &ordering, distances);
+
+    if args.local_search {
+        // Alternate between the two searches since each can open up moves for the other
+        let mut pass = 1;
+        loop {
+            info!("local search pass {}...", pass);
+            let two_opt_gain = two_opt(&mut ordering, distances);
+            log_statistics("2-opt", &ordering, distances);
+            let or_opt_gain = or_opt(&mut ordering, distances);
+            log_statistics("Or-opt", &ordering, distances);
+            if two_opt_gain + or_opt_gain == 0 {
+                break;
+            }
+            pass += 1;
+        }
+    }
+
+    if let Some(kmer_sample) = &args.kmer_sample {
+        info!("loading k-mer sample at {}", kmer_sample);
+        let kmer_sample = load_data_from_file::<KmerSample>(Path::new(kmer_sample));
+        if kmer_sample.file2taxid != file2taxid {
+            panic!("the k-mer sample was not created from the same file2taxid as the distances");
+        }
+
+        log_size_estimate("before block search", &kmer_sample, &ordering);
+        let saved = kmer_sample.block_local_search(&mut ordering, distances, args.neighbors);
+        info!("block search saved an estimated {:.0} blocks", saved);
+        log_statistics("block search", &ordering, distances);
+        log_size_estimate("after block search", &kmer_sample, &ordering);
+    }
+
+    ordering
+}
+
+fn log_statistics<D: FileDistances>(name: &str, ordering: &[usize], distances: &D) {
+    let (avg_dist, total_dist) = ordering_statistics(ordering, distances);
+    info!(
+        "{}: length of tour {}, average distance between files {}",
+        name, total_dist, avg_dist
+    );
+}
+
+fn log_size_estimate(name: &str, kmer_sample: &KmerSample, ordering: &[usize]) {
+    let estimate = kmer_sample.estimate_size(ordering);
+    info!(
+        "{}: estimated {:.0} blocks, {:.0} bytes as a database (.db), {:.0} bytes as a mapped database (.mdb)",
+        name, estimate.blocks, estimate.database_bytes, estimate.mapped_bytes
+    );
+}
@@ -1,16 +1,22 @@
 use clap::Parser;
 use indicatif::ParallelProgressIterator;
 use itertools::Itertools;
-use musk::consts::CANONICAL;
-use musk::io::{create_output_file, dump_data_to_file, load_string2taxid};
+use musk::checkpoint::{describe_file2taxid, WorkDirectory};
+use musk::distances::{NeighborDistances, PairwiseDistances};
+use musk::io::{create_output_file, dump_data_to_file, load_string2taxid, FileKind};
+use musk::sampling::Sampling;
+use musk::size_estimate::{sample_bitmap, KmerSample};
+use musk::sketch::{Sketch, SketchIndex};
 use musk::tracing::start_musk_tracing_subscriber;
 use musk::utility::create_bitmap;
 use rayon::prelude::*;
-use roaring::RoaringBitmap;
+use roaring::RoaringTreemap;
+use serde::{de::DeserializeOwned, Serialize};
 use std::path::Path;
 use tracing::info;
 
-/// Computes the pairwise distance (.pd) matrix (lower triangle) from the input file2taxid
+/// Computes the pairwise distance (.pd) matrix (lower triangle) from the input file2taxid.
+/// For many groups, the distances can be estimated from MinHash sketches instead.
 #[derive(Parser)]
 #[clap(version, about)]
 #[clap(author = "Trevor S. <trevor.schneggenburger@gmail.com>")]
@@ -19,12 +25,45 @@ struct Args {
     /// Length of k-mer to use in the database
     kmer_length: usize,
 
+    #[arg(long, action, verbatim_doc_comment)]
+    /// Use k-mers as they appear in the sequences rather than canonical k-mers.
+    /// By default, the smaller of a k-mer and its reverse complement is used.
+    non_canonical: bool,
+
     #[arg(short, long, default_value_t = std::env::current_dir().unwrap().to_str().unwrap().to_string(), verbatim_doc_comment)]
     /// Where to write the pairwise distance (.pd) file.
     /// If a file is provided, the extention '.musk.pd' is added.
     /// If a directory is provided, 'musk.pd' will be the file name.
+    /// With --nearest-neighbors, the extension is '.knn' instead of '.pd'.
     output_location: String,
 
+    #[arg(long, verbatim_doc_comment)]
+    /// Directory to save the bitmap and distances of each group in while computing.
+    /// If the run is interrupted, running the same command again resumes from the saved results.
+    /// A 'musk_work' directory is created inside it and removed once the distances are written.
+    work_directory: Option<String>,
+
+    #[arg(long, verbatim_doc_comment)]
+    /// Also write a k-mer sample (.ks) with this fraction of the k-mers of every group.
+    /// It is written next to the distances ('musk.ks') and lets musk-order and
+    /// musk-predict-size estimate the number of blocks of a database.
+    sample_fraction: Option<f64>,
+
+    #[arg(long, verbatim_doc_comment)]
+    /// Estimate the distances from bottom-k MinHash sketches with this many k-mer hashes per group
+    /// rather than computing them exactly. Only the sketches are kept in memory, so many more
+    /// groups fit in memory. The error of each distance shrinks with the square root of the
+    /// sketch size. The full matrix still compares every pair of groups, so its time grows
+    /// quadratically with the number of groups. With --nearest-neighbors, only groups that share
+    /// a sketch hash are compared.
+    sketch_size: Option<usize>,
+
+    #[arg(short = 'n', long, verbatim_doc_comment)]
+    /// Only keep the distances from each group to this many of its nearest groups.
+    /// A nearest neighbor distance (.knn) file is written instead of the full matrix, which grows
+    /// linearly rather than quadratically with the number of groups. It can be ordered by musk-order.
+    nearest_neighbors: Option<usize>,
+
     #[arg()]
     /// The file2taxid (.f2t) file
     file2taxid: String,
@@ -42,55 +81,269 @@ fn main() {
     let args = Args::parse();
     let file2taxid_path = Path::new(&args.file2taxid);
     let kmer_len = args.kmer_length;
+    let canonical = !args.non_canonical;
     let output_loc_path = Path::new(&args.output_location);
     let ref_dir_path = Path::new(&args.reference_directory);
 
     // Create the output file so it errors if an incorrect output file is provided before computation
-    let output_file = create_output_file(output_loc_path, "musk.pd");
+    let output_file = match args.nearest_neighbors {
+        None => create_output_file(output_loc_path, "musk.pd"),
+        Some(0) => panic!("the number of nearest neighbors must be at least 1"),
+        Some(_) => create_output_file(output_loc_path, "musk.knn"),
+    };
+    let sample_output_file = args.sample_fraction.map(|fraction| {
+        if !(fraction > 0.0 && fraction <= 1.0) {
+            panic!("the sample fraction must be in (0, 1], not {}", fraction);
+        }
+        create_output_file(output_loc_path, "musk.ks")
+    });
+    if args.sketch_size == Some(0) {
+        panic!("the sketch size must be at least 1");
+    }
 
     info!("loading file2taxid at {}", args.file2taxid);
     let file2taxid = load_string2taxid(file2taxid_path);
+    let work_dir = args.work_directory.as_ref().map(|work_directory| {
+        let mut settings = format!(
+            "musk-pairwise-distances of {}, k-mer length {}, canonical {}",
+            describe_file2taxid(&file2taxid),
+            kmer_len,
+            canonical
+        );
+        if let Some(num_neighbors) = args.nearest_neighbors {
+            settings.push_str(&format!(", nearest neighbors {}", num_neighbors));
+        }
+        // Sketches also hold the k-mers of the k-mer sample
+        if let Some(sketch_size) = args.sketch_size {
+            settings.push_str(&format!(
+                ", sketch size {}, sample fraction {:?}",
+                sketch_size, args.sample_fraction
+            ));
+        }
+        WorkDirectory::open(Path::new(work_directory), &settings)
+    });
+    if let Some(work_dir) = &work_dir {
+        let num_saved = (0..file2taxid.len())
+            .filter(|index| work_dir.contains(&row_name(*index)))
+            .count();
+        info!(
+            "{} of {} distance rows were already computed",
+            num_saved,
+            file2taxid.len()
+        );
+    }
 
-    info!("creating roaring bitmaps for each group...");
-    let bitmaps = file2taxid
-        .par_iter()
-        .progress()
-        .map(|(files, _taxid)| {
-            // Split the files up if they are grouped
-            let file_paths = files
-                .split("$")
-                .map(|file| ref_dir_path.join(file))
-                .collect_vec();
-
-            create_bitmap(file_paths, kmer_len, CANONICAL)
-        })
-        .collect::<Vec<RoaringBitmap>>();
-
-    info!("roaring bitmaps created, creating distance matrix...");
-    let distances = bitmaps
-        .par_iter()
-        .progress()
-        .enumerate()
-        .map(|(index_1, bitmap_1)| {
-            bitmaps[..=index_1]
+    let (distances, kmer_counts, samples) = match args.sketch_size {
+        None => {
+            info!("creating roaring bitmaps for each group...");
+            let bitmaps = file2taxid
+                .par_iter()
+                .enumerate()
+                .progress()
+                .map(|(index, (files, _taxid))| {
+                    let create = || group_bitmap(files, ref_dir_path, kmer_len, canonical);
+                    match &work_dir {
+                        Some(work_dir) => work_dir.load_or_create(&bitmap_name(index), create),
+                        None => create(),
+                    }
+                })
+                .collect::<Vec<RoaringTreemap>>();
+
+            info!("roaring bitmaps created, creating distance matrix...");
+            let distances = distance_rows(
+                &bitmaps,
+                work_dir.as_ref(),
+                args.nearest_neighbors,
+                |bitmap_1, bitmap_2| {
+                    let intersection_size = bitmap_1.intersection_len(bitmap_2);
+                    // |A| + |B| - (2 * |A & B|)
+                    (bitmap_1.len() + bitmap_2.len() - (2 * intersection_size)) as u32
+                },
+            );
+            let kmer_counts = bitmaps.iter().map(|bitmap| bitmap.len()).collect();
+            (distances, kmer_counts, bitmaps)
+        }
+        Some(sketch_size) => {
+            // Only the sketch (and the k-mers kept for the k-mer sample) of each group is kept in memory
+            info!(
+                "creating sketches of {} hashes for each group...",
+                sketch_size
+            );
+            let (sketches, samples): (Vec<Sketch>, Vec<RoaringTreemap>) = file2taxid
+                .par_iter()
+                .enumerate()
+                .progress()
+                .map(|(index, (files, _taxid))| {
+                    let create = || {
+                        let bitmap = group_bitmap(files, ref_dir_path, kmer_len, canonical);
+                        let sample = args
+                            .sample_fraction
+                            .map_or_else(RoaringTreemap::new, |fraction| {
+                                sample_bitmap(&bitmap, fraction)
+                            });
+                        (Sketch::from_bitmap(&bitmap, sketch_size), sample)
+                    };
+                    match &work_dir {
+                        Some(work_dir) => work_dir.load_or_create(&sketch_name(index), create),
+                        None => create(),
+                    }
+                })
+                .unzip();
+
+            info!("sketches created, estimating distance matrix...");
+            let distances = match args.nearest_neighbors {
+                // Groups that do not share a hash are only compared by their number of k-mers
+                Some(num_neighbors) => {
+                    let sketch_index = SketchIndex::new(&sketches);
+                    DistanceRows::Nearest(saved_rows(sketches.len(), work_dir.as_ref(), |index| {
+                        sketch_index
+                            .nearest(&sketches, index, num_neighbors)
+                            .into_iter()
+                            .map(|(distance, other)| (other, distance))
+                            .collect::<Vec<(u32, u32)>>()
+                    }))
+                }
+                None => distance_rows(&sketches, work_dir.as_ref(), None, |sketch_1, sketch_2| {
+                    sketch_1.hamming_distance(sketch_2)
+                }),
+            };
+            let kmer_counts = sketches.iter().map(|sketch| sketch.num_kmers()).collect();
+            (distances, kmer_counts, samples)
+        }
+    };
+
+    if let (Some(fraction), Some(sample_output_file)) = (args.sample_fraction, sample_output_file) {
+        info!("sampling {} of the k-mers...", fraction);
+        let kmer_sample =
+            KmerSample::from_bitmaps(&samples, fraction, kmer_len, canonical, file2taxid.clone());
+        info!(
+            "{} k-mers sampled, outputting to file...",
+            kmer_sample.kmer_files.len()
+        );
+        dump_data_to_file(&kmer_sample, FileKind::KmerSample, sample_output_file)
+            .expect("could not output k-mer sample to file");
+    }
+
+    info!("distance matrix completed! outputting to file...");
+    match distances {
+        DistanceRows::Matrix(distances) => {
+            let distances = PairwiseDistances {
+                kmer_len: Some(kmer_len),
+                canonical,
+                distances,
+                file2taxid,
+                kmer_counts: Some(kmer_counts),
+            };
+            dump_data_to_file(&distances, FileKind::PairwiseDistances, output_file)
+        }
+        DistanceRows::Nearest(nearest) => {
+            let distances =
+                NeighborDistances::from_nearest(kmer_len, canonical, nearest, file2taxid);
+            dump_data_to_file(&distances, FileKind::NeighborDistances, output_file)
+        }
+    }
+    .expect("could not output distances to file");
+
+    if let Some(work_dir) = work_dir {
+        info!("removing work directory...");
+        work_dir.remove();
+    }
+
+    info!("done!");
+}
+
+// Either the lower triangle of the distance matrix or the nearest neighbors of every item
+enum DistanceRows {
+    Matrix(Vec<Vec<u32>>),
+    Nearest(Vec<Vec<(u32, u32)>>),
+}
+
+// The distances between the items, only keeping the `num_neighbors` nearest of each if given
+fn distance_rows<T, F>(
+    items: &[T],
+    work_dir: Option<&WorkDirectory>,
+    num_neighbors: Option<usize>,
+    distance: F,
+) -> DistanceRows
+where
+    T: Sync,
+    F: Fn(&T, &T) -> u32 + Sync,
+{
+    match num_neighbors {
+        None => DistanceRows::Matrix(saved_rows(items.len(), work_dir, |index_1| {
+            items[..=index_1]
                 .iter()
                 .enumerate()
-                .map(|(index_2, bitmap_2)| {
+                .map(|(index_2, item_2)| {
                     if index_1 == index_2 {
                         0
                     } else {
-                        let intersection_size = bitmap_1.intersection_len(bitmap_2);
-                        // |A| + |B| - (2 * |A & B|)
-                        (bitmap_1.len() + bitmap_2.len() - (2 * intersection_size)) as u32
+                        distance(&items[index_1], item_2)
                     }
                 })
                 .collect::<Vec<u32>>()
+        })),
+        Some(num_neighbors) => {
+            DistanceRows::Nearest(saved_rows(items.len(), work_dir, |index_1| {
+                let mut nearest = items
+                    .iter()
+                    .enumerate()
+                    .filter(|(index_2, _item_2)| *index_2 != index_1)
+                    .map(|(index_2, item_2)| (distance(&items[index_1], item_2), index_2 as u32))
+                    .collect::<Vec<(u32, u32)>>();
+                if nearest.len() > num_neighbors {
+                    nearest.select_nth_unstable(num_neighbors);
+                    nearest.truncate(num_neighbors);
+                }
+                nearest.sort_unstable();
+                nearest
+                    .into_iter()
+                    .map(|(distance, index_2)| (index_2, distance))
+                    .collect::<Vec<(u32, u32)>>()
+            }))
+        }
+    }
+}
+
+// Creates each row (or loads it from the work directory)
+fn saved_rows<R, F>(num_rows: usize, work_dir: Option<&WorkDirectory>, create_row: F) -> Vec<R>
+where
+    R: Serialize + DeserializeOwned + Send,
+    F: Fn(usize) -> R + Sync,
+{
+    (0..num_rows)
+        .into_par_iter()
+        .progress_count(num_rows as u64)
+        .map(|index| match work_dir {
+            Some(work_dir) => work_dir.load_or_create(&row_name(index), || create_row(index)),
+            None => create_row(index),
         })
-        .collect::<Vec<Vec<u32>>>();
+        .collect()
+}
 
-    info!("distance matrix completed! outputting to file...");
-    dump_data_to_file(&(distances, file2taxid), output_file)
-        .expect("could not output distances to file");
+// The k-mers of a (possibly grouped) file2taxid entry
+fn group_bitmap(
+    files: &str,
+    ref_dir_path: &Path,
+    kmer_len: usize,
+    canonical: bool,
+) -> RoaringTreemap {
+    // Split the files up if they are grouped
+    let file_paths = files
+        .split("$")
+        .map(|file| ref_dir_path.join(file))
+        .collect_vec();
+    create_bitmap(file_paths, kmer_len, None, canonical, Sampling::All)
+}
 
-    info!("done!");
+fn bitmap_name(index: usize) -> String {
+    format!("bitmap_{}", index)
+}
+
+fn sketch_name(index: usize) -> String {
+    format!("sketch_{}", index)
+}
+
+fn row_name(index: usize) -> String {
+    format!("row_{}", index)
 }
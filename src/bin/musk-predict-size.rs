@@ -0,0 +1,86 @@
+use clap::Parser;
+use musk::io::{load_data_from_file, load_string2taxid};
+use musk::size_estimate::KmerSample;
+use musk::tracing::start_musk_tracing_subscriber;
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::{info, warn};
+
+/// Predicts the number of k-mers and blocks, and the size, of a database built from an
+/// ordered file2taxid (.o.f2t) file without building it.
+/// The prediction is made from a k-mer sample (.ks) created by musk-pairwise-distances.
+#[derive(Parser)]
+#[clap(version, about)]
+#[clap(author = "Trevor S. <trevor.schneggenburger@gmail.com>")]
+struct Args {
+    #[arg()]
+    /// The ordered file2taxid (.o.f2t) file
+    file2taxid: String,
+
+    #[arg()]
+    /// The k-mer sample (.ks) file
+    kmer_sample: String,
+}
+
+fn main() {
+    // Initialize the tracing subscriber to handle debug, info, warn, and error macro calls
+    start_musk_tracing_subscriber();
+
+    // Parse arguments from the command line
+    let args = Args::parse();
+    let file2taxid_path = Path::new(&args.file2taxid);
+    let kmer_sample_path = Path::new(&args.kmer_sample);
+
+    info!("loading file2taxid at {:?}", file2taxid_path);
+    let file2taxid = load_string2taxid(file2taxid_path);
+
+    info!("loading k-mer sample at {:?}", kmer_sample_path);
+    let kmer_sample = load_data_from_file::<KmerSample>(kmer_sample_path);
+
+    // The file2taxid may be any ordering of (a subset of) the groups in the k-mer sample
+    let sample_indices = kmer_sample
+        .file2taxid
+        .iter()
+        .enumerate()
+        .map(|(index, (files, _taxid))| (files.as_str(), index))
+        .collect::<HashMap<&str, usize>>();
+    let ordering = file2taxid
+        .iter()
+        .filter_map(|(files, _taxid)| {
+            let index = sample_indices.get(files.as_str()).copied();
+            if index.is_none() {
+                warn!("'{}' is not in the k-mer sample, it is left out", files);
+            }
+            index
+        })
+        .collect::<Vec<usize>>();
+
+    info!("estimating size...");
+    let estimate = kmer_sample.estimate_size(&ordering);
+
+    println!("k-mer length:\t{}", kmer_sample.kmer_len);
+    println!("canonical:\t{}", kmer_sample.canonical);
+    println!("sample fraction:\t{}", kmer_sample.fraction);
+    println!("sampled k-mers:\t{}", kmer_sample.kmer_files.len());
+    println!("groups:\t{}", ordering.len());
+    println!("estimated k-mers:\t{:.0}", estimate.kmers);
+    println!("estimated blocks:\t{:.0}", estimate.blocks);
+    println!(
+        "estimated blocks per k-mer:\t{:.3}",
+        if estimate.kmers == 0.0 {
+            0.0
+        } else {
+            estimate.blocks / estimate.kmers
+        }
+    );
+    println!(
+        "estimated database (.db) bytes:\t{:.0}",
+        estimate.database_bytes
+    );
+    println!(
+        "estimated mapped database (.mdb) bytes:\t{:.0}",
+        estimate.mapped_bytes
+    );
+
+    info!("done!");
+}
@@ -0,0 +1,133 @@
+use clap::Parser;
+use itertools::Itertools;
+use musk::database::Database;
+use musk::io::{create_separate_output_file, dump_data_to_file, load_data_from_file, FileKind};
+use musk::tracing::start_musk_tracing_subscriber;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use tracing::{info, warn};
+
+/// Creates a smaller musk database (.db/.cdb) with only some of the files of an existing database.
+/// The reference files are not needed, the k-mers are taken from the existing database.
+#[derive(Parser)]
+#[clap(version, about)]
+#[clap(author = "Trevor S. <trevor.schneggenburger@gmail.com>")]
+struct Args {
+    #[arg(short, long, default_value_t = std::env::current_dir().unwrap().to_str().unwrap().to_string(), verbatim_doc_comment)]
+    /// Where to write the subset database file, which is the same kind as the input database.
+    /// If a file is provided, the extension '.musk.db' (or '.musk.cdb' for a lossy database) is added.
+    /// If a directory is provided, 'musk.db' (or 'musk.cdb') will be the file name.
+    output_location: String,
+
+    #[arg(short, long, action, verbatim_doc_comment)]
+    /// The list contains tax ids instead of file names.
+    /// Every file with one of the tax ids is kept.
+    tax_ids: bool,
+
+    #[arg()]
+    /// The database (.db/.cdb) file to take the subset of
+    database: String,
+
+    #[arg(verbatim_doc_comment)]
+    /// The file names (or tax ids) to keep, one per line.
+    /// Only the first tab separated column is used, so a file2taxid (.f2t) file can be given.
+    /// Grouped files are kept if any file in the group is listed.
+    list: String,
+}
+
+fn main() {
+    // Initialize the tracing subscriber to handle debug, info, warn, and error macro calls
+    start_musk_tracing_subscriber();
+
+    // Parse arguments from the command line
+    let args = Args::parse();
+    let database_path = Path::new(&args.database);
+    let list_path = Path::new(&args.list);
+    let output_loc_path = Path::new(&args.output_location);
+
+    // The subset keeps the kind of the input, a lossy database stays lossy
+    let kind = Database::file_kind(database_path);
+    if !matches!(kind, FileKind::Database | FileKind::LossyDatabase) {
+        panic!(
+            "{:?} is a {}, only databases (.db/.cdb) can be subset",
+            database_path, kind
+        );
+    }
+
+    // Create the output file so it errors if an incorrect output file is provided before computation,
+    // but never over the database, which is not loaded yet
+    let output_file = create_separate_output_file(
+        output_loc_path,
+        &format!("musk.{}", kind.extension()),
+        &[database_path],
+    );
+
+    info!("loading list at {:?}", list_path);
+    let list_file = File::open(list_path)
+        .unwrap_or_else(|e| panic!("could not read list at {:?}: {}", list_path, e));
+    let list = BufReader::new(list_file)
+        .lines()
+        .map(|line| line.expect("could not read line of list"))
+        .filter_map(|line| {
+            line.split('\t')
+                .next()
+                .map(|entry| entry.trim().to_string())
+        })
+        .filter(|entry| !entry.is_empty())
+        .collect::<HashSet<String>>();
+
+    info!("loading database at {:?}", database_path);
+    let mut database = load_data_from_file::<Database>(database_path);
+
+    // Find the files to keep and which entries of the list matched at least one of them
+    let mut matched = HashSet::new();
+    let mut removed_files = vec![];
+    for (index, (files, tax_id)) in database.files().iter().zip(database.tax_ids()).enumerate() {
+        let listed = if args.tax_ids {
+            let tax_id = tax_id.to_string();
+            list.get(&tax_id).into_iter().collect_vec()
+        } else {
+            // A grouped entry is listed as a whole or by any of its files
+            std::iter::once(files.as_str())
+                .chain(files.split("$"))
+                .filter_map(|file| list.get(file))
+                .collect_vec()
+        };
+        if listed.is_empty() {
+            removed_files.push(index);
+        }
+        matched.extend(listed);
+    }
+
+    let unmatched = list
+        .iter()
+        .filter(|entry| !matched.contains(entry))
+        .collect_vec();
+    if !unmatched.is_empty() {
+        warn!(
+            "{} entries of the list are not in the database (e.g. {})",
+            unmatched.len(),
+            unmatched[0]
+        );
+    }
+
+    let kept_files = database.num_files() - removed_files.len();
+    if kept_files == 0 {
+        panic!("none of the files in the database are in the list");
+    }
+    info!(
+        "keeping {} files and removing {} files",
+        kept_files,
+        removed_files.len()
+    );
+
+    info!("removing files from the database...");
+    database.update(&removed_files, vec![], vec![], vec![]);
+
+    info!("dumping to file...");
+    dump_data_to_file(&database, kind, output_file).expect("could not serialize database to file");
+
+    info!("done!");
+}
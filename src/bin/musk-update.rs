@@ -0,0 +1,144 @@
+use clap::Parser;
+use indicatif::ParallelProgressIterator;
+use itertools::Itertools;
+use musk::database::Database;
+use musk::io::{
+    create_separate_output_file, dump_data_to_file, load_data_from_file, load_string2taxid,
+    FileKind,
+};
+use musk::tracing::start_musk_tracing_subscriber;
+use musk::utility::create_bitmap;
+use rayon::prelude::*;
+use roaring::RoaringTreemap;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tracing::{info, warn};
+
+/// Updates a musk database (.db/.cdb) file to match a new file2taxid (.f2t) file without rebuilding it.
+/// Files that are not in the file2taxid are removed and files that are not in the database are added.
+/// Added files are placed at the end of the database (unordered), so periodically rebuilding from an
+/// ordered file2taxid (.o.f2t) gives a smaller database.
+#[derive(Parser)]
+#[clap(version, about)]
+#[clap(author = "Trevor S. <trevor.schneggenburger@gmail.com>")]
+struct Args {
+    #[arg(short, long, default_value_t = std::env::current_dir().unwrap().to_str().unwrap().to_string(), verbatim_doc_comment)]
+    /// Where to write the updated database file, which is the same kind as the input database.
+    /// If a file is provided, the extension '.musk.db' (or '.musk.cdb' for a lossy database) is added.
+    /// If a directory is provided, 'musk.db' (or 'musk.cdb') will be the file name.
+    output_location: String,
+
+    #[arg()]
+    /// The database (.db/.cdb) file to update
+    database: String,
+
+    #[arg()]
+    /// The file2taxid (.f2t) file of every file that should be in the updated database
+    file2taxid: String,
+
+    #[arg()]
+    /// Directory with the FASTA files that are added to the database
+    reference_directory: String,
+}
+
+fn main() {
+    // Initialize the tracing subscriber to handle debug, info, warn, and error macro calls
+    start_musk_tracing_subscriber();
+
+    // Parse arguments from the command line
+    let args = Args::parse();
+    let database_path = Path::new(&args.database);
+    let file2taxid_path = Path::new(&args.file2taxid);
+    let output_loc_path = Path::new(&args.output_location);
+    let ref_dir_path = Path::new(&args.reference_directory);
+
+    // The updated database keeps the kind of the input, a lossy database stays lossy
+    let kind = Database::file_kind(database_path);
+    if !matches!(kind, FileKind::Database | FileKind::LossyDatabase) {
+        panic!(
+            "{:?} is a {}, only databases (.db/.cdb) can be updated",
+            database_path, kind
+        );
+    }
+
+    // Create the output file so it errors if an incorrect output file is provided before computation,
+    // but never over the database, which is not loaded yet
+    let output_file = create_separate_output_file(
+        output_loc_path,
+        &format!("musk.{}", kind.extension()),
+        &[database_path],
+    );
+
+    info!("loading database at {:?}", database_path);
+    let mut database = load_data_from_file::<Database>(database_path);
+
+    info!("loading file2taxid at {}", args.file2taxid);
+    let file2taxid = load_string2taxid(file2taxid_path);
+    let file2taxid_map = file2taxid
+        .iter()
+        .map(|(file, tax_id)| (file.as_str(), *tax_id))
+        .collect::<HashMap<&str, usize>>();
+
+    // Files in the database that are not in the file2taxid are removed
+    let removed_files = database
+        .files()
+        .iter()
+        .positions(|file| !file2taxid_map.contains_key(file.as_str()))
+        .collect_vec();
+    for (file, tax_id) in database.files().iter().zip(database.tax_ids()) {
+        match file2taxid_map.get(file.as_str()) {
+            Some(new_tax_id) if new_tax_id != tax_id => warn!(
+                "{} has tax id {} in the database but {} in the file2taxid, keeping {}",
+                file, tax_id, new_tax_id, tax_id
+            ),
+            _ => {}
+        }
+    }
+
+    // Files in the file2taxid that are not in the database are added (in file2taxid order)
+    let database_files = database
+        .files()
+        .iter()
+        .map(|file| file.as_str())
+        .collect::<HashSet<&str>>();
+    let (new_files, new_tax_ids): (Vec<String>, Vec<usize>) = file2taxid
+        .iter()
+        .filter(|(file, _tax_id)| !database_files.contains(file.as_str()))
+        .cloned()
+        .unzip();
+    info!(
+        "removing {} files and adding {} files",
+        removed_files.len(),
+        new_files.len()
+    );
+
+    info!("creating roaring bitmaps for each new group...");
+    let new_bitmaps = new_files
+        .par_iter()
+        .progress()
+        .map(|files| {
+            // Split the files up if they are grouped
+            let file_paths = files
+                .split("$")
+                .map(|file| ref_dir_path.join(file))
+                .collect_vec();
+
+            // Use the same k-mers as the database was built with
+            create_bitmap(
+                file_paths,
+                database.kmer_len(),
+                database.spaced_seed(),
+                database.canonical(),
+                database.sampling(),
+            )
+        })
+        .collect::<Vec<RoaringTreemap>>();
+
+    info!("updating database...");
+    database.update(&removed_files, new_bitmaps, new_files, new_tax_ids);
+
+    info!("dumping to file...");
+    dump_data_to_file(&database, kind, output_file).expect("could not serialize database to file");
+
+    info!("done!");
+}
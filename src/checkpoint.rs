@@ -0,0 +1,123 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+// The directory created inside the given directory, which only holds the saved results
+const WORK_DIR_NAME: &str = "musk_work";
+// The file in a work directory that records the settings it was created with
+const SETTINGS_FILE: &str = "settings";
+
+/// A directory where long running binaries save intermediate results (e.g. the bitmap of each
+/// file) so that an interrupted run resumes where it stopped when the same command is run again.
+pub struct WorkDirectory {
+    path: PathBuf,
+    // The names of the results saved or loaded, which are removed with the directory
+    names: Mutex<HashSet<String>>,
+}
+
+impl WorkDirectory {
+    /// Opens (or creates) the 'musk_work' directory inside `directory`.
+    /// `settings` must describe everything the saved results depend on. Panics if the directory
+    /// was created with different settings, since its results can not be reused, or if it is not
+    /// empty but was not created as a work directory.
+    pub fn open(directory: &Path, settings: &str) -> Self {
+        let path = directory.join(WORK_DIR_NAME);
+        let settings_path = path.join(SETTINGS_FILE);
+        if settings_path.exists() {
+            let saved_settings = fs::read_to_string(&settings_path).unwrap_or_else(|e| {
+                panic!(
+                    "could not read work directory settings at {:?}: {}",
+                    settings_path, e
+                )
+            });
+            if saved_settings != settings {
+                panic!(
+                    "the work directory at {:?} was created with different settings ({}), not ({}). Remove it or use another directory",
+                    path, saved_settings, settings
+                );
+            }
+            info!("resuming from work directory at {:?}", path);
+        } else {
+            let is_empty = fs::read_dir(&path).map_or(true, |mut entries| entries.next().is_none());
+            if !is_empty {
+                panic!(
+                    "{:?} is not empty but is not a work directory (there is no '{}' file). Remove it or use another directory",
+                    path, SETTINGS_FILE
+                );
+            }
+            fs::create_dir_all(&path).expect("could not create work directory");
+            fs::write(&settings_path, settings).expect("could not write work directory settings");
+            info!("created work directory at {:?}", path);
+        }
+
+        WorkDirectory {
+            path,
+            names: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Returns whether a result was saved under `name`
+    pub fn contains(&self, name: &str) -> bool {
+        self.path.join(name).exists()
+    }
+
+    /// Returns the result saved under `name`, or creates and saves it if there is none
+    pub fn load_or_create<T, F>(&self, name: &str, create: F) -> T
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> T,
+    {
+        self.names.lock().unwrap().insert(name.to_string());
+        let path = self.path.join(name);
+        if let Ok(file) = File::open(&path) {
+            match bincode::deserialize_from(BufReader::new(file)) {
+                Ok(data) => return data,
+                Err(e) => warn!("could not load {:?} ({}), creating it again", path, e),
+            }
+        }
+
+        let data = create();
+        // Write to a temporary file first so that an interrupted write is never loaded
+        let temp_path = self.path.join(format!("{}.tmp", name));
+        let mut buf_writer = BufWriter::new(
+            File::create(&temp_path).expect("could not create file in work directory"),
+        );
+        bincode::serialize_into(&mut buf_writer, &data)
+            .and_then(|()| buf_writer.flush().map_err(bincode::Error::from))
+            .expect("could not write to work directory");
+        fs::rename(&temp_path, &path).expect("could not write to work directory");
+
+        data
+    }
+
+    /// Removes the results saved or loaded in this run, then the work directory if nothing else is in it
+    pub fn remove(self) {
+        for name in self.names.into_inner().unwrap() {
+            let path = self.path.join(name);
+            if let Err(e) = fs::remove_file(&path) {
+                warn!("could not remove {:?}: {}", path, e);
+            }
+        }
+        fs::remove_file(self.path.join(SETTINGS_FILE))
+            .and_then(|()| fs::remove_dir(&self.path))
+            .unwrap_or_else(|e| warn!("could not remove work directory at {:?}: {}", self.path, e));
+    }
+}
+
+/// A short description of a file2taxid, so that a work directory is not resumed with other files
+pub fn describe_file2taxid(file2taxid: &[(String, usize)]) -> String {
+    let mut hasher = crc32fast::Hasher::new();
+    for (files, tax_id) in file2taxid {
+        hasher.update(files.as_bytes());
+        hasher.update(&tax_id.to_le_bytes());
+    }
+    format!(
+        "{} groups, checksum {:08x}",
+        file2taxid.len(),
+        hasher.finalize()
+    )
+}
@@ -0,0 +1,354 @@
+use itertools::Itertools;
+use rayon::prelude::*;
+use roaring::RoaringTreemap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::database::{kmer_space_size, sparse_naive_rles};
+use crate::kmer_iter::SpacedSeed;
+use crate::mapped::{MappedMetadata, MappedWriter};
+use crate::rle::RunLengthEncoding;
+use crate::sampling::Sampling;
+
+// The kmer space is split into 4^BUCKET_PREFIX_LEN buckets by the leading nucleotides of each kmer
+const BUCKET_PREFIX_LEN: usize = 4;
+// The most nucleotides a bucket is split on at once, so at most 4^MAX_SPLIT_LEN files are open
+const MAX_SPLIT_LEN: usize = 4;
+
+// Estimated bytes used by each set bit when compressing a bucket (naive and compressed blocks)
+const BYTES_PER_BIT: u64 = 4;
+// Estimated bytes used by each distinct kmer when compressing a bucket (both rles and the kmer)
+const BYTES_PER_KMER: u64 = 64;
+
+// The kmers of every file that fall in one bucket, spilled to disk as they are added
+struct Bucket {
+    writer: BufWriter<File>,
+    bitmap_bytes: u64,
+    set_bits: u64,
+}
+
+impl Bucket {
+    fn create(path: &Path) -> io::Result<Self> {
+        Ok(Bucket {
+            writer: BufWriter::new(File::create(path)?),
+            bitmap_bytes: 0,
+            set_bits: 0,
+        })
+    }
+
+    // Each record is the file index, the number of bytes, then the serialized bitmap
+    fn write_bitmap(&mut self, index: usize, bitmap: &RoaringTreemap) -> io::Result<()> {
+        let mut bitmap_bytes = Vec::with_capacity(bitmap.serialized_size());
+        bitmap.serialize_into(&mut bitmap_bytes)?;
+
+        self.writer.write_all(&(index as u64).to_le_bytes())?;
+        self.writer
+            .write_all(&(bitmap_bytes.len() as u64).to_le_bytes())?;
+        self.writer.write_all(&bitmap_bytes)?;
+        self.bitmap_bytes += bitmap_bytes.len() as u64;
+        self.set_bits += bitmap.len();
+        Ok(())
+    }
+
+    // Flushes the bucket and closes its file
+    fn spill(mut self, path: PathBuf, prefix_len: usize) -> io::Result<SpilledBucket> {
+        self.writer.flush()?;
+        Ok(SpilledBucket {
+            path,
+            prefix_len,
+            bitmap_bytes: self.bitmap_bytes,
+            set_bits: self.set_bits,
+        })
+    }
+}
+
+// A finished bucket of the kmers that share their leading `prefix_len` nucleotides
+struct SpilledBucket {
+    path: PathBuf,
+    prefix_len: usize,
+    bitmap_bytes: u64,
+    set_bits: u64,
+}
+
+impl SpilledBucket {
+    // An upper bound on the memory used to compress the bucket
+    fn memory(&self, kmer_len: usize) -> u64 {
+        let bucket_kmers = 4_u64.saturating_pow((kmer_len - self.prefix_len) as u32);
+        self.bitmap_bytes
+            + self.set_bits * BYTES_PER_BIT
+            + self.set_bits.min(bucket_kmers) * BYTES_PER_KMER
+    }
+}
+
+/// Builds a mapped database (.mdb) without holding the kmers of every file in memory at once.
+///
+/// Bitmaps are added one file at a time and split into buckets (by kmer prefix) on disk.
+/// When finished, buckets that would not fit in the memory limit are split again on longer
+/// prefixes, consecutive buckets are grouped into chunks that fit in the memory limit, and
+/// each chunk is compressed across all files and appended to the database in kmer order.
+pub struct ChunkedBuilder {
+    kmer_len: usize,
+    spaced_seed: Option<SpacedSeed>,
+    canonical: bool,
+    sampling: Sampling,
+    work_dir: PathBuf,
+    buckets: Vec<Mutex<Bucket>>,
+    file_kmer_counts: Vec<AtomicU64>,
+}
+
+impl ChunkedBuilder {
+    /// Creates the bucket files in `work_dir`, which is created if it does not exist
+    pub fn new(
+        num_files: usize,
+        kmer_len: usize,
+        spaced_seed: Option<SpacedSeed>,
+        canonical: bool,
+        sampling: Sampling,
+        work_dir: &Path,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(work_dir)?;
+        let num_buckets = 4_usize.pow(BUCKET_PREFIX_LEN.min(kmer_len) as u32);
+        let buckets = (0..num_buckets)
+            .map(|bucket| Ok(Mutex::new(Bucket::create(&bucket_path(work_dir, bucket))?)))
+            .collect::<io::Result<Vec<Mutex<Bucket>>>>()?;
+
+        Ok(ChunkedBuilder {
+            kmer_len,
+            spaced_seed,
+            canonical,
+            sampling,
+            work_dir: work_dir.to_path_buf(),
+            buckets,
+            file_kmer_counts: (0..num_files).map(|_| AtomicU64::new(0)).collect(),
+        })
+    }
+
+    fn bucket(&self, kmer: u64) -> usize {
+        let prefix_len = BUCKET_PREFIX_LEN.min(self.kmer_len);
+        (kmer >> (2 * (self.kmer_len - prefix_len))) as usize
+    }
+
+    /// Adds the kmers of the file at `index`. Files can be added in any order (and in parallel).
+    pub fn add_bitmap(&self, index: usize, bitmap: &RoaringTreemap) -> io::Result<()> {
+        self.file_kmer_counts[index].store(bitmap.len(), Ordering::Relaxed);
+
+        // The bitmap is sorted, so the kmers of each bucket are consecutive
+        for (bucket, kmers) in &bitmap.iter().chunk_by(|kmer| self.bucket(*kmer)) {
+            let bucket_bitmap = RoaringTreemap::from_sorted_iter(kmers).unwrap();
+            self.buckets[bucket]
+                .lock()
+                .unwrap()
+                .write_bitmap(index, &bucket_bitmap)?;
+        }
+
+        Ok(())
+    }
+
+    /// Compresses every chunk and writes the mapped database, then removes the bucket files.
+    /// `memory_limit` (in bytes) bounds the estimated memory used to compress each chunk, only a
+    /// single kmer (which cannot be split) may go over it.
+    /// Returns the estimated memory of each chunk.
+    pub fn finish(
+        mut self,
+        files: Vec<String>,
+        tax_ids: Vec<usize>,
+        memory_limit: u64,
+        output_file: File,
+    ) -> io::Result<Vec<u64>> {
+        let prefix_len = BUCKET_PREFIX_LEN.min(self.kmer_len);
+        let mut unsplit = vec![];
+        for (bucket_num, bucket) in std::mem::take(&mut self.buckets).into_iter().enumerate() {
+            let bucket = bucket.into_inner().unwrap();
+            unsplit.push(bucket.spill(bucket_path(&self.work_dir, bucket_num), prefix_len)?);
+        }
+
+        // Split buckets on longer prefixes until each fits in the memory limit, keeping kmer order
+        let mut buckets = vec![];
+        let mut single_kmers_over_limit = 0;
+        unsplit.reverse();
+        while let Some(bucket) = unsplit.pop() {
+            let memory = bucket.memory(self.kmer_len);
+            if bucket.set_bits == 0 {
+                fs::remove_file(&bucket.path)?;
+            } else if memory <= memory_limit {
+                buckets.push((bucket, memory));
+            } else if bucket.prefix_len == self.kmer_len {
+                single_kmers_over_limit += 1;
+                buckets.push((bucket, memory));
+            } else {
+                let sub_buckets = self.split_bucket(bucket, memory, memory_limit)?;
+                unsplit.extend(sub_buckets.into_iter().rev());
+            }
+        }
+        if single_kmers_over_limit > 0 {
+            warn!(
+                "{} kmers are each estimated to use more than the memory limit, they are compressed alone",
+                single_kmers_over_limit
+            );
+        }
+
+        // Group consecutive buckets so that each chunk is in kmer order
+        let mut chunks: Vec<(Vec<SpilledBucket>, u64)> = vec![];
+        for (bucket, memory) in buckets {
+            match chunks.last_mut() {
+                Some((buckets, chunk_memory)) if *chunk_memory + memory <= memory_limit => {
+                    buckets.push(bucket);
+                    *chunk_memory += memory;
+                }
+                _ => chunks.push((vec![bucket], memory)),
+            }
+        }
+        info!(
+            "compressing {} buckets in {} chunks...",
+            chunks
+                .iter()
+                .map(|(buckets, _memory)| buckets.len())
+                .sum::<usize>(),
+            chunks.len()
+        );
+
+        let mut mapped_writer = MappedWriter::new(self.kmer_len, &self.work_dir)?;
+        let mut chunk_memories = vec![];
+        for (chunk_num, (buckets, chunk_memory)) in chunks.into_iter().enumerate() {
+            debug!(
+                "chunk {} has {} buckets (estimated {} bytes)",
+                chunk_num,
+                buckets.len(),
+                chunk_memory
+            );
+            let kmers_and_rles = buckets
+                .par_iter()
+                .map(|bucket| compress_bucket(&bucket.path, files.len()))
+                .collect::<io::Result<Vec<Vec<(u64, RunLengthEncoding)>>>>()?;
+            for (kmer, rle) in kmers_and_rles.into_iter().flatten() {
+                mapped_writer.push(kmer, rle.get_raw_blocks())?;
+            }
+            for bucket in buckets {
+                fs::remove_file(&bucket.path)?;
+            }
+            chunk_memories.push(chunk_memory);
+        }
+
+        let total_sampled_kmers =
+            kmer_space_size(self.kmer_len, self.canonical) * self.sampling.density(self.kmer_len);
+        let p_values = self
+            .file_kmer_counts
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed) as f64 / total_sampled_kmers)
+            .collect_vec();
+
+        let metadata = MappedMetadata {
+            canonical: self.canonical,
+            kmer_len: self.kmer_len,
+            spaced_seed: self.spaced_seed,
+            sampling: self.sampling,
+            files,
+            tax_ids,
+            p_values,
+            num_kmers: 0,
+            num_blocks: 0,
+        };
+        info!("writing mapped database...");
+        mapped_writer.finish(metadata, output_file)?;
+        Ok(chunk_memories)
+    }
+
+    // Splits a bucket on enough more nucleotides that a sub bucket of average size would fit in
+    // the memory limit, then removes it. Returns the non-empty sub buckets in kmer order.
+    fn split_bucket(
+        &self,
+        bucket: SpilledBucket,
+        memory: u64,
+        memory_limit: u64,
+    ) -> io::Result<Vec<SpilledBucket>> {
+        let max_split_len = MAX_SPLIT_LEN.min(self.kmer_len - bucket.prefix_len);
+        let mut split_len = 1;
+        while split_len < max_split_len
+            && memory_limit.saturating_mul(4_u64.pow(split_len as u32)) < memory
+        {
+            split_len += 1;
+        }
+        let prefix_len = bucket.prefix_len + split_len;
+        let shift = 2 * (self.kmer_len - prefix_len);
+        let mask = 4_usize.pow(split_len as u32) - 1;
+        let sub_path = |sub_bucket: usize| {
+            let mut path = bucket.path.clone().into_os_string();
+            path.push(format!("_{}", sub_bucket));
+            PathBuf::from(path)
+        };
+
+        // Sub buckets are only created once they have kmers
+        let mut sub_buckets = (0..=mask).map(|_| None).collect::<Vec<Option<Bucket>>>();
+        read_bucket(&bucket.path, |index, bitmap| {
+            for (sub_bucket, kmers) in &bitmap
+                .iter()
+                .chunk_by(|kmer| (kmer >> shift) as usize & mask)
+            {
+                let sub_bitmap = RoaringTreemap::from_sorted_iter(kmers).unwrap();
+                match &mut sub_buckets[sub_bucket] {
+                    Some(sub_bucket) => sub_bucket.write_bitmap(index, &sub_bitmap)?,
+                    None => {
+                        let mut new_bucket = Bucket::create(&sub_path(sub_bucket))?;
+                        new_bucket.write_bitmap(index, &sub_bitmap)?;
+                        sub_buckets[sub_bucket] = Some(new_bucket);
+                    }
+                }
+            }
+            Ok(())
+        })?;
+        fs::remove_file(&bucket.path)?;
+
+        sub_buckets
+            .into_iter()
+            .enumerate()
+            .filter_map(|(sub_bucket, bucket)| {
+                bucket.map(|bucket| bucket.spill(sub_path(sub_bucket), prefix_len))
+            })
+            .collect()
+    }
+}
+
+// Reads the bitmaps of every file in a bucket and compresses them into sorted rles
+fn compress_bucket(path: &Path, num_files: usize) -> io::Result<Vec<(u64, RunLengthEncoding)>> {
+    let mut file_bitmaps = vec![RoaringTreemap::new(); num_files];
+    read_bucket(path, |index, bitmap| {
+        file_bitmaps[index] |= bitmap;
+        Ok(())
+    })?;
+
+    Ok(sparse_naive_rles(file_bitmaps)
+        .into_iter()
+        .map(|(kmer, naive_rle)| (kmer, naive_rle.to_rle()))
+        .collect())
+}
+
+// Calls `f` with the file index and bitmap of each record in a bucket
+fn read_bucket<F: FnMut(usize, RoaringTreemap) -> io::Result<()>>(
+    path: &Path,
+    mut f: F,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut index_bytes = [0_u8; 8];
+    loop {
+        match reader.read_exact(&mut index_bytes) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let mut len_bytes = [0_u8; 8];
+        reader.read_exact(&mut len_bytes)?;
+        let bitmap_reader = (&mut reader).take(u64::from_le_bytes(len_bytes));
+        f(
+            u64::from_le_bytes(index_bytes) as usize,
+            RoaringTreemap::deserialize_from(bitmap_reader)?,
+        )?;
+    }
+}
+
+fn bucket_path(work_dir: &Path, bucket: usize) -> PathBuf {
+    work_dir.join(format!("bucket_{}", bucket))
+}
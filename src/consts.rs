@@ -1,8 +1,6 @@
 use crate::big_exp_float::BigExpFloat;
 use serde::{Deserialize, Serialize};
 
-pub const CANONICAL: bool = true;
-
 #[derive(Serialize, Deserialize)]
 pub struct BinomialConsts {
     pub(crate) gamma_r: f64,
@@ -1,23 +1,128 @@
-use num_traits::{One, Zero};
+use itertools::{Either, Itertools};
+use num_traits::Zero;
 use rayon::prelude::*;
-use roaring::RoaringBitmap;
-use serde::{Deserialize, Serialize};
+use roaring::RoaringTreemap;
+use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
 use statrs::distribution::{Binomial, DiscreteCDF};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
 use std::{collections::HashMap, time::Instant, u16, u32};
+use taxonomy::GeneralTaxonomy;
 use tracing::{debug, info};
 
 use crate::{
     big_exp_float::BigExpFloat,
     binomial_sf::sf,
     consts::BinomialConsts,
-    kmer_iter::KmerIter,
+    io::{load_data_from_file, read_file_header, FileKind, MuskFile},
+    kmer_index::{IndexKind, KmerIndex},
+    kmer_iter::{base2int, iter_kmers, SpacedSeed},
+    lca::lowest_common_ancestor,
+    mapped::{dump_mapped_to_file, load_mapped_from_file, MappedBlocks, MappedMetadata},
     rle::{
-        Block, BlockIter, NaiveRunLengthEncoding, RunLengthEncoding, MAX_RUN, MAX_UNCOMPRESSED_BITS,
+        collect_indices, Block, BlockIter, NaiveRunLengthEncoding, RunLengthEncoding,
+        RunLengthEncodingBlockIter, MAX_RUN, MAX_UNCOMPRESSED_BITS,
     },
+    sampling::Sampling,
 };
 
-#[derive(Serialize, Deserialize)]
+/// A file that was tested for a read along with its probability and number of k-mer hits
+pub struct Candidate<'a> {
+    pub file: &'a str,
+    pub tax_id: usize,
+    pub probability: BigExpFloat,
+    pub num_hits: u64,
+}
+
+/// The result of classifying a read (or pair of reads)
+pub struct Classification<'a> {
+    /// The assigned tax id, if the read was classified.
+    /// This is the lowest common ancestor of all significant files if a taxonomy was used.
+    pub tax_id: Option<usize>,
+    /// Files that had more hits than expected, ranked from lowest to highest probability
+    pub candidates: Vec<Candidate<'a>>,
+    /// The total number of k-mers queried
+    pub num_queries: u64,
+}
+
+impl<'a> Classification<'a> {
+    /// Returns the file with the lowest probability and the assigned tax id, if the read was classified
+    pub fn assignment(&self) -> Option<(&'a str, usize)> {
+        self.tax_id.map(|tax_id| (self.candidates[0].file, tax_id))
+    }
+
+    pub fn best(&self) -> Option<&Candidate<'a>> {
+        self.candidates.first()
+    }
+
+    pub fn runner_up(&self) -> Option<&Candidate<'a>> {
+        self.candidates.get(1)
+    }
+
+    /// Returns (at most) the `n` most significant files, ranked from lowest to highest probability.
+    /// Files that did not have more hits than expected are never included.
+    pub fn top_n(&self, n: usize) -> &[Candidate<'a>] {
+        &self.candidates[..n.min(self.candidates.len())]
+    }
+}
+
+/// Where the run length encoding of each kmer is stored
+enum KmerBlocks {
+    /// Deserialized from a database (.db/.cdb) file
+    InMemory {
+        rles: Box<[RunLengthEncoding]>,
+        kmer_index: KmerIndex,
+    },
+    /// Queried directly from a memory mapped database (.mdb) file
+    Mapped(MappedBlocks),
+}
+
+#[derive(Deserialize)]
+#[serde(from = "SerializedDatabase")]
 pub struct Database {
+    canonical: bool,
+    consts: BinomialConsts,
+    files: Box<[String]>,
+    tax_ids: Box<[usize]>,
+    kmer_len: usize,
+    spaced_seed: Option<SpacedSeed>,
+    sampling: Sampling,
+    kmer_blocks: KmerBlocks,
+    p_values: Box<[f64]>,
+}
+
+impl MuskFile for Database {
+    const KINDS: &'static [FileKind] = &[FileKind::Database, FileKind::LossyDatabase];
+
+    fn migrate<R: Read>(version: u32, reader: R) -> bincode::Result<Self> {
+        debug!("migrating database from file format version {}", version);
+        // Only files without a header (version 0) have an older layout
+        let database = bincode::deserialize_from::<R, SerializedDatabaseV0>(reader)?;
+        Ok(SerializedDatabase::from(database).into())
+    }
+}
+
+// The layout of a database (.db/.cdb) file
+#[derive(Deserialize)]
+struct SerializedDatabase {
+    canonical: bool,
+    consts: BinomialConsts,
+    files: Box<[String]>,
+    rles: Box<[RunLengthEncoding]>,
+    tax_ids: Box<[usize]>,
+    kmer_len: usize,
+    spaced_seed: Option<SpacedSeed>,
+    sampling: Sampling,
+    kmer_index: KmerIndex,
+    p_values: Box<[f64]>,
+}
+
+// The layout of a database (.db/.cdb) file written before musk files had a header (version 0)
+#[derive(Deserialize)]
+struct SerializedDatabaseV0 {
     canonical: bool,
     consts: BinomialConsts,
     files: Box<[String]>,
@@ -28,20 +133,259 @@ pub struct Database {
     p_values: Box<[f64]>,
 }
 
+impl From<SerializedDatabaseV0> for SerializedDatabase {
+    fn from(database: SerializedDatabaseV0) -> Self {
+        // Every contiguous kmer was stored in a hashmap index
+        SerializedDatabase {
+            canonical: database.canonical,
+            consts: database.consts,
+            files: database.files,
+            rles: database.rles,
+            tax_ids: database.tax_ids,
+            kmer_len: database.kmer_len,
+            spaced_seed: None,
+            sampling: Sampling::All,
+            kmer_index: KmerIndex::HashMap(database.kmer_to_rle_index),
+            p_values: database.p_values,
+        }
+    }
+}
+
+impl From<SerializedDatabase> for Database {
+    fn from(database: SerializedDatabase) -> Self {
+        Database {
+            canonical: database.canonical,
+            consts: database.consts,
+            files: database.files,
+            tax_ids: database.tax_ids,
+            kmer_len: database.kmer_len,
+            spaced_seed: database.spaced_seed,
+            sampling: database.sampling,
+            kmer_blocks: KmerBlocks::InMemory {
+                rles: database.rles,
+                kmer_index: database.kmer_index,
+            },
+            p_values: database.p_values,
+        }
+    }
+}
+
+// Serializes in the layout of `SerializedDatabase` so that mapped databases can also be written
+impl Serialize for Database {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("SerializedDatabase", 10)?;
+        state.serialize_field("canonical", &self.canonical)?;
+        state.serialize_field("consts", &self.consts)?;
+        state.serialize_field("files", &self.files)?;
+        state.serialize_field("rles", &SerializeRles(&self.kmer_blocks))?;
+        state.serialize_field("tax_ids", &self.tax_ids)?;
+        state.serialize_field("kmer_len", &self.kmer_len)?;
+        state.serialize_field("spaced_seed", &self.spaced_seed)?;
+        state.serialize_field("sampling", &self.sampling)?;
+        state.serialize_field("kmer_index", &SerializeKmerIndex(self))?;
+        state.serialize_field("p_values", &self.p_values)?;
+        state.end()
+    }
+}
+
+// Serializes in the layout of `RunLengthEncoding`
+#[derive(Serialize)]
+#[serde(rename = "RunLengthEncoding")]
+struct SerializeRle<'a> {
+    blocks: &'a [u16],
+}
+
+struct SerializeRles<'a>(&'a KmerBlocks);
+
+impl Serialize for SerializeRles<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            KmerBlocks::InMemory { rles, .. } => rles.serialize(serializer),
+            KmerBlocks::Mapped(mapped_blocks) => {
+                serializer.collect_seq((0..mapped_blocks.len()).map(|position| SerializeRle {
+                    blocks: mapped_blocks.blocks(position),
+                }))
+            }
+        }
+    }
+}
+
+struct SerializeKmerIndex<'a>(&'a Database);
+
+impl Serialize for SerializeKmerIndex<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match &self.0.kmer_blocks {
+            KmerBlocks::InMemory { kmer_index, .. } => kmer_index.serialize(serializer),
+            KmerBlocks::Mapped(mapped_blocks) => KmerIndex::from_kmers(
+                IndexKind::HashMap,
+                self.0.kmer_len,
+                (0..mapped_blocks.len()).map(|position| mapped_blocks.kmer(position)),
+            )
+            .serialize(serializer),
+        }
+    }
+}
+
 impl Database {
     pub fn num_files(&self) -> usize {
         self.files.len()
     }
 
+    pub fn tax_ids(&self) -> &[usize] {
+        &self.tax_ids
+    }
+
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    pub fn p_values(&self) -> &[f64] {
+        &self.p_values
+    }
+
+    pub fn kmer_len(&self) -> usize {
+        self.kmer_len
+    }
+
+    pub fn canonical(&self) -> bool {
+        self.canonical
+    }
+
+    /// The spaced seed kmers were extracted with, if any (the kmer length is its weight)
+    pub fn spaced_seed(&self) -> Option<&SpacedSeed> {
+        self.spaced_seed.as_ref()
+    }
+
+    /// Which kmers of the references were stored (reads are sampled the same way)
+    pub fn sampling(&self) -> Sampling {
+        self.sampling
+    }
+
+    /// The number of distinct k-mers in the database
+    pub fn num_kmers(&self) -> usize {
+        match &self.kmer_blocks {
+            KmerBlocks::InMemory { rles, .. } => rles.len(),
+            KmerBlocks::Mapped(mapped_blocks) => mapped_blocks.len(),
+        }
+    }
+
+    /// The kind of kmer index, `None` for a mapped database
+    pub fn index_kind(&self) -> Option<IndexKind> {
+        match &self.kmer_blocks {
+            KmerBlocks::InMemory { kmer_index, .. } => Some(kmer_index.kind()),
+            KmerBlocks::Mapped(_) => None,
+        }
+    }
+
+    /// Returns the raw blocks of the run length encoding at a position (in 0..num_kmers)
+    pub fn rle_blocks(&self, position: usize) -> &[u16] {
+        match &self.kmer_blocks {
+            KmerBlocks::InMemory { rles, .. } => rles[position].get_raw_blocks(),
+            KmerBlocks::Mapped(mapped_blocks) => mapped_blocks.blocks(position),
+        }
+    }
+
+    // Iterates over every kmer and the position of its run length encoding (in no particular order)
+    fn kmers_and_positions(&self) -> impl Iterator<Item = (u64, usize)> + '_ {
+        match &self.kmer_blocks {
+            KmerBlocks::InMemory { kmer_index, .. } => Either::Left(
+                kmer_index
+                    .iter()
+                    .map(|(kmer, rle_index)| (kmer, rle_index as usize)),
+            ),
+            KmerBlocks::Mapped(mapped_blocks) => Either::Right(
+                (0..mapped_blocks.len()).map(|position| (mapped_blocks.kmer(position), position)),
+            ),
+        }
+    }
+
+    /// Loads a database (.db/.cdb) into memory or memory maps a mapped database (.mdb)
+    pub fn load(path: &Path) -> Self {
+        let mut buf_reader = BufReader::new(
+            File::open(path).unwrap_or_else(|e| panic!("could not open file at {:?}: {}", path, e)),
+        );
+
+        // Any errors reading the header are reported when loading the file
+        match read_file_header(&mut buf_reader) {
+            Ok(Some(header)) if header.kind == FileKind::MappedDatabase => {
+                let (metadata, mapped_blocks) = load_mapped_from_file(path).unwrap_or_else(|e| {
+                    panic!(
+                        "failed to load mapped database from file at {:?}: {}",
+                        path, e
+                    )
+                });
+                Database {
+                    canonical: metadata.canonical,
+                    consts: BinomialConsts::new(),
+                    files: metadata.files.into_boxed_slice(),
+                    tax_ids: metadata.tax_ids.into_boxed_slice(),
+                    kmer_len: metadata.kmer_len,
+                    spaced_seed: metadata.spaced_seed,
+                    sampling: metadata.sampling,
+                    kmer_blocks: KmerBlocks::Mapped(mapped_blocks),
+                    p_values: metadata.p_values.into_boxed_slice(),
+                }
+            }
+            _ => load_data_from_file::<Database>(path),
+        }
+    }
+
+    /// The kind of a database file (.db/.cdb/.mdb) from its header.
+    /// Files written before headers were added are told apart by their extension.
+    pub fn file_kind(path: &Path) -> FileKind {
+        let mut buf_reader = BufReader::new(
+            File::open(path).unwrap_or_else(|e| panic!("could not open file at {:?}: {}", path, e)),
+        );
+        match read_file_header(&mut buf_reader)
+            .unwrap_or_else(|e| panic!("could not read header of {:?}: {}", path, e))
+        {
+            Some(header) => header.kind,
+            None => FileKind::from_path(path).unwrap_or(FileKind::Database),
+        }
+    }
+
+    /// Writes the database as a mapped database (.mdb) file
+    pub fn dump_mapped(&self, file: File) -> io::Result<()> {
+        // The kmers must be written in sorted order
+        let kmers_and_positions = self.kmers_and_positions().sorted_unstable().collect_vec();
+
+        let metadata = MappedMetadata {
+            canonical: self.canonical,
+            kmer_len: self.kmer_len,
+            spaced_seed: self.spaced_seed.clone(),
+            sampling: self.sampling,
+            files: self.files.to_vec(),
+            tax_ids: self.tax_ids.to_vec(),
+            p_values: self.p_values.to_vec(),
+            num_kmers: kmers_and_positions.len() as u64,
+            num_blocks: (0..self.num_kmers())
+                .map(|position| self.rle_blocks(position).len() as u64)
+                .sum(),
+        };
+
+        dump_mapped_to_file(
+            &metadata,
+            || {
+                kmers_and_positions
+                    .iter()
+                    .map(|(kmer, position)| (*kmer, self.rle_blocks(*position)))
+            },
+            file,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn from(
-        file_bitmaps: Vec<RoaringBitmap>,
+        file_bitmaps: Vec<RoaringTreemap>,
         canonical: bool,
         files: Vec<String>,
         tax_ids: Vec<usize>,
         kmer_len: usize,
+        spaced_seed: Option<SpacedSeed>,
+        sampling: Sampling,
+        index_kind: IndexKind,
     ) -> Self {
-        let total_canonical_kmers =
-            (4_usize.pow(kmer_len as u32) - 4_usize.pow(kmer_len.div_ceil(2) as u32)) / 2;
+        let total_sampled_kmers = kmer_space_size(kmer_len, canonical) * sampling.density(kmer_len);
 
         // Calculate probability of success (p) for each file with a debug logging step in
         // the middle
@@ -49,36 +393,21 @@ impl Database {
             .par_iter()
             .map(|bitmap| bitmap.len())
             .collect::<Vec<u64>>();
-        debug!("total bits set: {}", bitmap_sizes.iter().sum::<u64>());
+        let total_bits = bitmap_sizes.iter().sum::<u64>();
+        debug!("total bits set: {}", total_bits);
         let p_values = bitmap_sizes
             .into_par_iter()
-            .map(|size| size as f64 / total_canonical_kmers as f64)
+            .map(|size| size as f64 / total_sampled_kmers)
             .collect::<Box<[f64]>>();
 
-        // Initialize the naive RLEs to be the maximum possible size
-        let mut kmer_to_naive_rle =
-            vec![NaiveRunLengthEncoding::new(); 4_usize.pow(kmer_len as u32)];
-
         // Construct all naive kmer RLEs from the bitmaps
+        // Only allocate a naive RLE for every possible kmer if there are more bits set than kmers
         info!("constructing naive runs...");
-        for (index, bitmap) in file_bitmaps.into_iter().enumerate() {
-            for kmer in bitmap {
-                kmer_to_naive_rle[kmer as usize].push(index);
-            }
-        }
-
-        // Filter out naive rles that do not have any blocks
-        let filtered_kmers_and_rles = kmer_to_naive_rle
-            .into_iter()
-            .enumerate()
-            .filter_map(|(kmer, naive_rle)| {
-                if naive_rle.num_of_blocks() == 0 {
-                    None
-                } else {
-                    Some((kmer as u32, naive_rle))
-                }
-            })
-            .collect::<Vec<(u32, NaiveRunLengthEncoding)>>();
+        let filtered_kmers_and_rles = if (4_u128.pow(kmer_len as u32)) <= total_bits as u128 {
+            dense_naive_rles(file_bitmaps, kmer_len)
+        } else {
+            sparse_naive_rles(file_bitmaps)
+        };
 
         // Log information about the number of naive runs
         let naive_run_count = filtered_kmers_and_rles
@@ -92,7 +421,7 @@ impl Database {
         let kmers_and_rles = filtered_kmers_and_rles
             .into_par_iter()
             .map(|(kmer, naive_rle)| (kmer, naive_rle.to_rle()))
-            .collect::<Vec<(u32, RunLengthEncoding)>>();
+            .collect::<Vec<(u64, RunLengthEncoding)>>();
 
         // Log information about the number of compressed runs
         let compressed_block_num = kmers_and_rles
@@ -104,29 +433,239 @@ impl Database {
             compressed_block_num
         );
 
-        // Create a hashmap over the kmers, indicating where each kmer rle is in the vector
-        let mut kmer_to_rle_index = HashMap::with_capacity(kmers_and_rles.len());
+        // Create an index over the kmers, indicating where each kmer rle is in the vector
+        info!("creating kmer index ({:?})...", index_kind);
+        let kmer_index = KmerIndex::from_kmers(
+            index_kind,
+            kmer_len,
+            kmers_and_rles.iter().map(|(kmer, _rle)| *kmer),
+        );
         let rles = kmers_and_rles
             .into_iter()
-            .enumerate()
-            .map(|(index, (kmer, rle))| {
-                kmer_to_rle_index.insert(kmer, index as u32);
-                rle
-            })
+            .map(|(_kmer, rle)| rle)
             .collect::<Box<[RunLengthEncoding]>>();
 
         Database {
             canonical,
             consts: BinomialConsts::new(),
             files: files.into_boxed_slice(),
-            rles,
             tax_ids: tax_ids.into_boxed_slice(),
             kmer_len,
-            kmer_to_rle_index,
+            spaced_seed,
+            sampling,
+            kmer_blocks: KmerBlocks::InMemory { rles, kmer_index },
             p_values,
         }
     }
 
+    /// Removes the files at `removed_files` and adds new files (with their bitmaps) to the end.
+    /// The bitmaps must be created with the same kmer settings as the database.
+    /// The remaining files keep their relative order, so only the edited run length encodings change.
+    pub fn update(
+        &mut self,
+        removed_files: &[usize],
+        new_bitmaps: Vec<RoaringTreemap>,
+        new_files: Vec<String>,
+        new_tax_ids: Vec<usize>,
+    ) {
+        let (rles, kmer_index) = match &mut self.kmer_blocks {
+            KmerBlocks::InMemory { rles, kmer_index } => (rles, kmer_index),
+            KmerBlocks::Mapped(_) => panic!("a mapped database can not be updated"),
+        };
+
+        // Find the new index of every existing file, if it is kept
+        let mut is_removed = vec![false; self.files.len()];
+        for index in removed_files {
+            is_removed[*index] = true;
+        }
+        let mut new_index = vec![None; self.files.len()];
+        let mut num_kept = 0_usize;
+        for (index, removed) in is_removed.iter().enumerate() {
+            if !removed {
+                new_index[index] = Some(num_kept);
+                num_kept += 1;
+            }
+        }
+
+        // Find the (new) indices of the new files that each kmer is in
+        info!("collecting kmers of {} new files...", new_bitmaps.len());
+        let mut kmer_to_new_indices: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (offset, bitmap) in new_bitmaps.into_iter().enumerate() {
+            for kmer in bitmap {
+                kmer_to_new_indices
+                    .entry(kmer)
+                    .or_default()
+                    .push(num_kept + offset);
+            }
+        }
+
+        // Edit the run length encodings of existing kmers, dropping kmers that are in no file
+        info!("editing run length encodings...");
+        let mut kmers_and_rles = kmer_index
+            .iter()
+            .collect_vec()
+            .into_par_iter()
+            .filter_map(|(kmer, rle_index)| {
+                let mut naive_rle = NaiveRunLengthEncoding::new();
+                rles[rle_index as usize]
+                    .iter()
+                    .filter_map(|index| new_index[index])
+                    .chain(
+                        kmer_to_new_indices
+                            .get(&kmer)
+                            .into_iter()
+                            .flatten()
+                            .copied(),
+                    )
+                    .for_each(|index| naive_rle.push(index));
+                if naive_rle.num_of_blocks() == 0 {
+                    None
+                } else {
+                    Some((kmer, naive_rle.to_rle()))
+                }
+            })
+            .collect::<Vec<(u64, RunLengthEncoding)>>();
+        let num_existing_kmers = kmers_and_rles.len();
+
+        // Add the kmers that were not in the database
+        kmers_and_rles.extend(
+            kmer_to_new_indices
+                .into_iter()
+                .filter(|(kmer, _indices)| kmer_index.get(*kmer).is_none())
+                .map(|(kmer, indices)| {
+                    let mut naive_rle = NaiveRunLengthEncoding::new();
+                    indices.into_iter().for_each(|index| naive_rle.push(index));
+                    (kmer, naive_rle.to_rle())
+                }),
+        );
+        debug!(
+            "{} kmers kept, {} kmers added",
+            num_existing_kmers,
+            kmers_and_rles.len() - num_existing_kmers
+        );
+
+        info!("recreating kmer index ({:?})...", kmer_index.kind());
+        *kmer_index = KmerIndex::from_kmers(
+            kmer_index.kind(),
+            self.kmer_len,
+            kmers_and_rles.iter().map(|(kmer, _rle)| *kmer),
+        );
+        *rles = kmers_and_rles
+            .into_iter()
+            .map(|(_kmer, rle)| rle)
+            .collect::<Box<[RunLengthEncoding]>>();
+
+        // Update the files and tax ids to match the new indices
+        let (files, tax_ids) = self
+            .files
+            .iter()
+            .zip(self.tax_ids.iter())
+            .zip(is_removed)
+            .filter(|(_file_and_tax_id, removed)| !removed)
+            .map(|((file, tax_id), _removed)| (file.clone(), *tax_id))
+            .chain(new_files.into_iter().zip(new_tax_ids))
+            .unzip::<String, usize, Vec<String>, Vec<usize>>();
+        self.files = files.into_boxed_slice();
+        self.tax_ids = tax_ids.into_boxed_slice();
+
+        self.recompute_p_values();
+    }
+
+    /// Appends the files of another database built with the same kmer settings.
+    /// The file indices of `other` are offset by the number of files in this database.
+    pub fn merge(&mut self, other: &Database) {
+        if self.kmer_len != other.kmer_len
+            || self.canonical != other.canonical
+            || self.spaced_seed != other.spaced_seed
+            || self.sampling != other.sampling
+        {
+            panic!(
+                "databases built with different k-mer settings can not be merged: k = {}, canonical = {}, spaced seed = {:?}, sampling = {} and k = {}, canonical = {}, spaced seed = {:?}, sampling = {}",
+                self.kmer_len,
+                self.canonical,
+                self.spaced_seed.as_ref().map(|spaced_seed| spaced_seed.to_string()),
+                self.sampling,
+                other.kmer_len,
+                other.canonical,
+                other.spaced_seed.as_ref().map(|spaced_seed| spaced_seed.to_string()),
+                other.sampling,
+            );
+        }
+        let (rles, kmer_index) = match &mut self.kmer_blocks {
+            KmerBlocks::InMemory { rles, kmer_index } => (rles, kmer_index),
+            KmerBlocks::Mapped(_) => panic!("can not merge into a mapped database"),
+        };
+        let offset = self.files.len();
+
+        // Find where each kmer of the other database is
+        let other_kmer_to_position = other.kmers_and_positions().collect::<HashMap<u64, usize>>();
+
+        // Merge the run length encodings of kmers in this database
+        info!("merging run length encodings...");
+        let mut kmers_and_rles = kmer_index
+            .iter()
+            .collect_vec()
+            .into_par_iter()
+            .map(|(kmer, rle_index)| {
+                let rle = &rles[rle_index as usize];
+                match other_kmer_to_position.get(&kmer) {
+                    None => (kmer, RunLengthEncoding::from(rle.get_raw_blocks().into())),
+                    Some(position) => {
+                        let mut naive_rle = NaiveRunLengthEncoding::new();
+                        rle.iter()
+                            .chain(
+                                collect_indices(other.rle_blocks(*position))
+                                    .into_iter()
+                                    .map(|index| index as usize + offset),
+                            )
+                            .for_each(|index| naive_rle.push(index));
+                        (kmer, naive_rle.to_rle())
+                    }
+                }
+            })
+            .collect::<Vec<(u64, RunLengthEncoding)>>();
+
+        // Add the kmers that are only in the other database
+        kmers_and_rles.extend(
+            other_kmer_to_position
+                .into_iter()
+                .filter(|(kmer, _position)| kmer_index.get(*kmer).is_none())
+                .map(|(kmer, position)| {
+                    let mut naive_rle = NaiveRunLengthEncoding::new();
+                    collect_indices(other.rle_blocks(position))
+                        .into_iter()
+                        .for_each(|index| naive_rle.push(index as usize + offset));
+                    (kmer, naive_rle.to_rle())
+                }),
+        );
+
+        info!("recreating kmer index ({:?})...", kmer_index.kind());
+        *kmer_index = KmerIndex::from_kmers(
+            kmer_index.kind(),
+            self.kmer_len,
+            kmers_and_rles.iter().map(|(kmer, _rle)| *kmer),
+        );
+        *rles = kmers_and_rles
+            .into_iter()
+            .map(|(_kmer, rle)| rle)
+            .collect::<Box<[RunLengthEncoding]>>();
+
+        self.files = self
+            .files
+            .iter()
+            .chain(other.files.iter())
+            .cloned()
+            .collect();
+        self.tax_ids = self
+            .tax_ids
+            .iter()
+            .chain(other.tax_ids.iter())
+            .copied()
+            .collect();
+
+        self.recompute_p_values();
+    }
+
     pub fn compute_loookup_table(&self, n_max: u64) -> Vec<BigExpFloat> {
         // Including 0 hits, there are n_max + 1 total possible values for the number of hits
         let possible_hit_numbers = (n_max + 1) as usize;
@@ -178,10 +717,14 @@ impl Database {
                 _ => None,
             }
         }
+        let rles = match &mut self.kmer_blocks {
+            KmerBlocks::InMemory { rles, .. } => rles,
+            KmerBlocks::Mapped(_) => panic!("a mapped database cannot be lossy compressed"),
+        };
+
         info!("performing lossy compresseion...");
 
-        let total_set_bits = self
-            .rles
+        let total_set_bits = rles
             .par_iter()
             .map(|rle| {
                 rle.block_iters()
@@ -194,14 +737,13 @@ impl Database {
             .sum::<usize>();
         debug!("total set bits before compression {}", total_set_bits);
 
-        let total_blocks = self
-            .rles
+        let total_blocks = rles
             .par_iter()
             .map(|rle| rle.num_of_blocks())
             .sum::<usize>();
         debug!("total blocks before compression {}", total_blocks);
 
-        self.rles.par_iter_mut().for_each(|current_rle| {
+        rles.par_iter_mut().for_each(|current_rle| {
             // variable to hold the new lossy compressed blocks as u16s
             let mut compressed_blocks = vec![];
 
@@ -330,9 +872,8 @@ impl Database {
             *current_rle = RunLengthEncoding::from(raw_compressed_blocks);
         }); // end par_iter_mut/for_each
 
-        // self.rles has now been mutably updated with the requested lossy compression
-        let total_set_bits = self
-            .rles
+        // rles has now been mutably updated with the requested lossy compression
+        let total_set_bits = rles
             .par_iter()
             .map(|rle| {
                 rle.block_iters()
@@ -345,8 +886,7 @@ impl Database {
             .sum::<usize>();
         debug!("total set bits after compression {}", total_set_bits);
 
-        let total_blocks = self
-            .rles
+        let total_blocks = rles
             .par_iter()
             .map(|rle| rle.num_of_blocks())
             .sum::<usize>();
@@ -357,13 +897,14 @@ impl Database {
     }
 
     fn recompute_p_values(&mut self) -> () {
-        let total_canonical_kmers =
-            (4_usize.pow(self.kmer_len as u32) - 4_usize.pow(self.kmer_len.div_ceil(2) as u32)) / 2;
+        let total_sampled_kmers =
+            kmer_space_size(self.kmer_len, self.canonical) * self.sampling.density(self.kmer_len);
 
         let mut file2kmer_num = vec![0_usize; self.num_files()];
 
-        self.rles.iter().for_each(|rle| {
-            rle.block_iters().for_each(|block_iter| match block_iter {
+        (0..self.num_kmers()).for_each(|position| {
+            let block_iters = RunLengthEncodingBlockIter::from_blocks(self.rle_blocks(position));
+            block_iters.for_each(|block_iter| match block_iter {
                 BlockIter::BitIter((bit_iter, start_i)) => {
                     bit_iter.map(|i| i + start_i).for_each(|i| {
                         file2kmer_num[i] += 1;
@@ -379,7 +920,7 @@ impl Database {
 
         let p_values = file2kmer_num
             .into_par_iter()
-            .map(|kmer_num| kmer_num as f64 / total_canonical_kmers as f64)
+            .map(|kmer_num| kmer_num as f64 / total_sampled_kmers)
             .collect::<Box<[f64]>>();
 
         self.p_values = p_values;
@@ -391,43 +932,178 @@ impl Database {
         cutoff_threshold: BigExpFloat,
         n_max: u64,
         lookup_table: &Vec<BigExpFloat>,
-    ) -> (Option<(&str, usize)>, (f64, f64)) {
+        taxonomy: Option<&GeneralTaxonomy>,
+    ) -> (Classification<'_>, (f64, f64)) {
         // Create a vector to store the hits
         let mut num_hits = vec![0_u64; self.num_files()];
 
+        let hit_lookup_start = Instant::now();
+        let n_total = self.count_hits(read, &mut num_hits);
+        let hit_lookup_time = hit_lookup_start.elapsed().as_secs_f64();
+
+        let prob_calc_start = Instant::now();
+        let classification = self.classify_hits(
+            &num_hits,
+            n_total,
+            cutoff_threshold,
+            n_max,
+            lookup_table,
+            taxonomy,
+        );
+        let prob_calc_time = prob_calc_start.elapsed().as_secs_f64();
+
+        (classification, (hit_lookup_time, prob_calc_time))
+    }
+
+    /// Classifies a pair of mates as a single fragment.
+    /// Hits from both mates are pooled before performing one binomial test per file.
+    pub fn classify_pair(
+        &self,
+        read_1: &[u8],
+        read_2: &[u8],
+        cutoff_threshold: BigExpFloat,
+        n_max: u64,
+        lookup_table: &[BigExpFloat],
+        taxonomy: Option<&GeneralTaxonomy>,
+    ) -> (Classification<'_>, (f64, f64)) {
+        // Create a vector to store the hits of both mates
+        let mut num_hits = vec![0_u64; self.num_files()];
+
+        let hit_lookup_start = Instant::now();
+        let n_total =
+            self.count_hits(read_1, &mut num_hits) + self.count_hits(read_2, &mut num_hits);
+        let hit_lookup_time = hit_lookup_start.elapsed().as_secs_f64();
+
+        let prob_calc_start = Instant::now();
+        let classification = self.classify_hits(
+            &num_hits,
+            n_total,
+            cutoff_threshold,
+            n_max,
+            lookup_table,
+            taxonomy,
+        );
+        let prob_calc_time = prob_calc_start.elapsed().as_secs_f64();
+
+        (classification, (hit_lookup_time, prob_calc_time))
+    }
+
+    /// Returns the tax id of every k-mer position in the read (for Kraken style output).
+    /// Each k-mer is assigned the lowest common ancestor of the files that contain it, or 0 if no file does.
+    /// Positions where the k-mer contains an ambiguous nucleotide are `None`.
+    /// If the database was sampled, k-mers that were not sampled from any reference are 0.
+    pub fn kmer_tax_ids(&self, read: &[u8], taxonomy: &GeneralTaxonomy) -> Vec<Option<usize>> {
+        // A spaced kmer covers the span of the seed rather than its length
+        let span = self
+            .spaced_seed
+            .as_ref()
+            .map_or(self.kmer_len, |spaced_seed| spaced_seed.span());
+        if read.len() < span {
+            return vec![];
+        }
+
+        // Count the ambiguous nucleotides before each position to find which k-mers contain one
+        let mut ambiguous_before = vec![0_usize; read.len() + 1];
+        for (position, base) in read.iter().enumerate() {
+            ambiguous_before[position + 1] =
+                ambiguous_before[position] + base2int(*base).is_none() as usize;
+        }
+
+        // The kmer iterator skips exactly the k-mers that contain an ambiguous nucleotide
+        let mut kmer_iter = iter_kmers(
+            read,
+            self.kmer_len,
+            self.spaced_seed.as_ref(),
+            self.canonical,
+        )
+        .map(|k| k as u64);
+
+        (0..=read.len() - span)
+            .map(|start| {
+                if ambiguous_before[start + span] > ambiguous_before[start] {
+                    return None;
+                }
+                let kmer = kmer_iter
+                    .next()
+                    .expect("kmer iterator ended before the end of the read");
+
+                match self.get_blocks(kmer) {
+                    None => Some(0),
+                    Some(blocks) => {
+                        let tax_ids = collect_indices(blocks)
+                            .into_iter()
+                            .map(|index| self.tax_ids[index as usize])
+                            .unique()
+                            .collect_vec();
+                        if tax_ids.len() == 1 {
+                            Some(tax_ids[0])
+                        } else {
+                            Some(lowest_common_ancestor(taxonomy, tax_ids).unwrap_or(0))
+                        }
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn get_blocks(&self, kmer: u64) -> Option<&[u16]> {
+        match &self.kmer_blocks {
+            KmerBlocks::InMemory { rles, kmer_index } => kmer_index
+                .get(kmer)
+                .map(|rle_index| rles[rle_index as usize].get_raw_blocks()),
+            KmerBlocks::Mapped(mapped_blocks) => mapped_blocks
+                .position(kmer)
+                .map(|position| mapped_blocks.blocks(position)),
+        }
+    }
+
+    // Adds the hits of every kmer in the read to `num_hits` and returns the number of kmers queried
+    fn count_hits(&self, read: &[u8], num_hits: &mut [u64]) -> u64 {
         // Create a variable to track the total number of kmers queried
         let mut n_total = 0_u64;
 
-        let hit_lookup_start = Instant::now();
-        // For each kmer in the read
-        for kmer in KmerIter::from(read, self.kmer_len, self.canonical).map(|k| k as u32) {
+        // For each (sampled) kmer in the read
+        for kmer in self.sampling.kmers(
+            read,
+            self.kmer_len,
+            self.spaced_seed.as_ref(),
+            self.canonical,
+        ) {
             // Lookup the RLE and decompress
-            if let Some(rle_index) = self.kmer_to_rle_index.get(&kmer) {
-                self.rles[*rle_index as usize].block_iters().for_each(
-                    |block_iter| match block_iter {
-                        BlockIter::BitIter((bit_iter, start_i)) => {
-                            bit_iter.map(|i| i + start_i).for_each(|i| {
-                                num_hits[i] += 1;
-                            });
-                        }
-                        BlockIter::Range((start_i, end_i)) => {
-                            num_hits[start_i..end_i].iter_mut().for_each(|count| {
-                                *count += 1;
-                            });
-                        }
-                    },
-                );
+            if let Some(blocks) = self.get_blocks(kmer) {
+                let block_iters = RunLengthEncodingBlockIter::from_blocks(blocks);
+                block_iters.for_each(|block_iter| match block_iter {
+                    BlockIter::BitIter((bit_iter, start_i)) => {
+                        bit_iter.map(|i| i + start_i).for_each(|i| {
+                            num_hits[i] += 1;
+                        });
+                    }
+                    BlockIter::Range((start_i, end_i)) => {
+                        num_hits[start_i..end_i].iter_mut().for_each(|count| {
+                            *count += 1;
+                        });
+                    }
+                });
             }
             // Increment the total number of queries
             n_total += 1;
         }
-        let hit_lookup_time = hit_lookup_start.elapsed().as_secs_f64();
 
-        // Classify the hits
-        // Would do this using min_by_key but the Ord trait is difficult to implement for float types
-        let prob_calc_start = Instant::now();
-        let (mut lowest_prob_index, mut lowest_prob) = (0, BigExpFloat::one());
-        for (index, probability) in num_hits
+        n_total
+    }
+
+    // Performs the binomial test for each file given the number of hits and queries
+    fn classify_hits(
+        &self,
+        num_hits: &[u64],
+        n_total: u64,
+        cutoff_threshold: BigExpFloat,
+        n_max: u64,
+        lookup_table: &[BigExpFloat],
+        taxonomy: Option<&GeneralTaxonomy>,
+    ) -> Classification<'_> {
+        // Compute the probability of every file that could be significant
+        let mut candidates = num_hits
             .iter()
             .zip(self.p_values.iter())
             .enumerate()
@@ -468,25 +1144,124 @@ impl Database {
                     None
                 }
             })
-        {
-            // For each index that we computed, compare to find the lowest probability
-            // If (for whatever reason) two probabilities are the same, this will use the first one
-            if probability < lowest_prob {
-                (lowest_prob_index, lowest_prob) = (index, probability);
+            .map(|(index, probability)| Candidate {
+                file: &self.files[index],
+                tax_id: self.tax_ids[index],
+                probability,
+                num_hits: num_hits[index],
+            })
+            .collect::<Vec<Candidate>>();
+
+        // Rank the candidates from lowest to highest probability
+        // Would do this using sort_by_key but the Ord trait is difficult to implement for float types
+        // The sort is stable, so if two probabilities are the same the first file is ranked higher
+        // A NaN probability (which is never significant) is ranked last instead of panicking
+        candidates.sort_by(|a, b| {
+            (a.probability.is_nan().cmp(&b.probability.is_nan())).then_with(|| {
+                a.probability
+                    .partial_cmp(&b.probability)
+                    .unwrap_or(Ordering::Equal)
+            })
+        });
+
+        // If a taxonomy was provided, resolve all significant files to their lowest common
+        // ancestor. Otherwise, use the tax id of the file with the lowest probability.
+        let tax_id = match candidates.first() {
+            Some(best) if best.probability < cutoff_threshold => Some(
+                taxonomy
+                    .and_then(|taxonomy| {
+                        lowest_common_ancestor(
+                            taxonomy,
+                            candidates
+                                .iter()
+                                .take_while(|candidate| candidate.probability < cutoff_threshold)
+                                .map(|candidate| candidate.tax_id),
+                        )
+                    })
+                    .unwrap_or(best.tax_id),
+            ),
+            _ => None,
+        };
+
+        Classification {
+            tax_id,
+            candidates,
+            num_queries: n_total,
+        }
+    }
+}
+
+// The number of possible (canonical) kmers of a length (at most MAX_KMER_LEN), computed as a u128
+// so that 4^k can not overflow and returned as a float for the probability calculations
+pub(crate) fn kmer_space_size(kmer_len: usize, canonical: bool) -> f64 {
+    if canonical {
+        ((4_u128.pow(kmer_len as u32) - 4_u128.pow(kmer_len.div_ceil(2) as u32)) / 2) as f64
+    } else {
+        4_u128.pow(kmer_len as u32) as f64
+    }
+}
+
+// Creates the naive RLEs by allocating one for every possible kmer
+fn dense_naive_rles(
+    file_bitmaps: Vec<RoaringTreemap>,
+    kmer_len: usize,
+) -> Vec<(u64, NaiveRunLengthEncoding)> {
+    // Initialize the naive RLEs to be the maximum possible size
+    let mut kmer_to_naive_rle = vec![NaiveRunLengthEncoding::new(); 4_usize.pow(kmer_len as u32)];
+
+    for (index, bitmap) in file_bitmaps.into_iter().enumerate() {
+        for kmer in bitmap {
+            kmer_to_naive_rle[kmer as usize].push(index);
+        }
+    }
+
+    // Filter out naive rles that do not have any blocks
+    kmer_to_naive_rle
+        .into_iter()
+        .enumerate()
+        .filter_map(|(kmer, naive_rle)| {
+            if naive_rle.num_of_blocks() == 0 {
+                None
+            } else {
+                Some((kmer as u64, naive_rle))
+            }
+        })
+        .collect::<Vec<(u64, NaiveRunLengthEncoding)>>()
+}
+
+// Creates the naive RLEs by merging the (sorted) kmers of every file
+// Only kmers that are in at least one file are allocated a naive RLE
+pub(crate) fn sparse_naive_rles(
+    file_bitmaps: Vec<RoaringTreemap>,
+) -> Vec<(u64, NaiveRunLengthEncoding)> {
+    let mut kmer_iters = file_bitmaps
+        .into_iter()
+        .map(|bitmap| bitmap.into_iter())
+        .collect_vec();
+
+    // The heap holds the next kmer of each file, popping the smallest kmer (then file index) first
+    let mut heap = BinaryHeap::with_capacity(kmer_iters.len());
+    for (index, kmer_iter) in kmer_iters.iter_mut().enumerate() {
+        if let Some(kmer) = kmer_iter.next() {
+            heap.push(Reverse((kmer, index)));
+        }
+    }
+
+    let mut kmers_and_naive_rles: Vec<(u64, NaiveRunLengthEncoding)> = vec![];
+    while let Some(Reverse((kmer, index))) = heap.pop() {
+        match kmers_and_naive_rles.last_mut() {
+            Some((last_kmer, naive_rle)) if *last_kmer == kmer => naive_rle.push(index),
+            _ => {
+                let mut naive_rle = NaiveRunLengthEncoding::new();
+                naive_rle.push(index);
+                kmers_and_naive_rles.push((kmer, naive_rle));
             }
         }
-        let prob_calc_time = prob_calc_start.elapsed().as_secs_f64();
 
-        if lowest_prob < cutoff_threshold {
-            (
-                Some((
-                    &*self.files[lowest_prob_index],
-                    self.tax_ids[lowest_prob_index],
-                )),
-                (hit_lookup_time, prob_calc_time),
-            )
-        } else {
-            (None, (hit_lookup_time, prob_calc_time))
+        if let Some(next_kmer) = kmer_iters[index].next() {
+            heap.push(Reverse((next_kmer, index)));
         }
     }
+
+    kmers_and_naive_rles
 }
@@ -0,0 +1,470 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
+use tracing::{debug, warn};
+
+use crate::io::{FileKind, MuskFile};
+
+/// Pairwise distances (lower triangle) along with the file2taxid and k-mer settings they were
+/// computed with
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PairwiseDistances {
+    /// `None` for files written before musk files had a header, which did not record it
+    pub kmer_len: Option<usize>,
+    pub canonical: bool,
+    /// Row i holds the distances from file i to files 0..=i
+    pub distances: Vec<Vec<u32>>,
+    pub file2taxid: Vec<(String, usize)>,
+    /// The number of distinct k-mers of each file, used to normalize the distances.
+    /// `None` for files written before musk files had a header and distances imported without them.
+    pub kmer_counts: Option<Vec<u64>>,
+}
+
+impl MuskFile for PairwiseDistances {
+    const KINDS: &'static [FileKind] = &[FileKind::PairwiseDistances];
+
+    fn migrate<R: Read>(version: u32, reader: R) -> bincode::Result<Self> {
+        debug!("migrating distances from file format version {}", version);
+        // Files without a header (version 0) only stored the distances and file2taxid, and k-mers
+        // were always canonical
+        let (distances, file2taxid): (Vec<Vec<u32>>, Vec<(String, usize)>) =
+            bincode::deserialize_from(reader)?;
+        Ok(PairwiseDistances {
+            kmer_len: None,
+            canonical: true,
+            distances,
+            file2taxid,
+            kmer_counts: None,
+        })
+    }
+}
+
+impl PairwiseDistances {
+    /// Panics if the distances were computed with different k-mer settings,
+    /// since distances computed with different settings can not be combined
+    pub fn check_settings(&self, kmer_len: usize, canonical: bool) {
+        match self.kmer_len {
+            Some(distances_kmer_len) if distances_kmer_len != kmer_len => panic!(
+                "the distances were computed with a k-mer length of {}, not {}",
+                distances_kmer_len, kmer_len
+            ),
+            Some(_) => {}
+            None => warn!(
+                "the distances do not record their k-mer length, make sure it was {}",
+                kmer_len
+            ),
+        }
+        if self.canonical != canonical {
+            panic!(
+                "the distances were computed with {} k-mers, but {} k-mers were requested",
+                canonical_str(self.canonical),
+                canonical_str(canonical)
+            );
+        }
+    }
+}
+
+/// The distances from every file to its nearest neighbors (a sparse k-nearest neighbor graph).
+/// Unlike the lower triangle of `PairwiseDistances`, it grows linearly with the number of files.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct NeighborDistances {
+    pub kmer_len: usize,
+    pub canonical: bool,
+    /// The neighbors of each file and their distances, sorted by neighbor so that the distance to
+    /// a neighbor is found with a binary search. Every file is also a neighbor of its neighbors.
+    pub neighbors: Vec<Vec<(u32, u32)>>,
+    /// The distance used between files that are not neighbors, the largest distance to a neighbor
+    pub far_distance: u32,
+    pub file2taxid: Vec<(String, usize)>,
+}
+
+impl MuskFile for NeighborDistances {
+    const KINDS: &'static [FileKind] = &[FileKind::NeighborDistances];
+}
+
+impl NeighborDistances {
+    /// Creates the graph from the nearest neighbors of each file, adding the reverse of each edge
+    pub fn from_nearest(
+        kmer_len: usize,
+        canonical: bool,
+        nearest: Vec<Vec<(u32, u32)>>,
+        file2taxid: Vec<(String, usize)>,
+    ) -> Self {
+        let mut neighbors = nearest.clone();
+        for (file, file_nearest) in nearest.into_iter().enumerate() {
+            for (neighbor, distance) in file_nearest {
+                neighbors[neighbor as usize].push((file as u32, distance));
+            }
+        }
+        for file_neighbors in neighbors.iter_mut() {
+            file_neighbors.sort_unstable();
+            file_neighbors.dedup_by_key(|(neighbor, _distance)| *neighbor);
+        }
+        let far_distance = neighbors
+            .iter()
+            .flatten()
+            .map(|(_neighbor, distance)| *distance)
+            .max()
+            .unwrap_or(0);
+
+        NeighborDistances {
+            kmer_len,
+            canonical,
+            neighbors,
+            far_distance,
+            file2taxid,
+        }
+    }
+}
+
+/// How the distance between two files is measured when exporting distances
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum DistanceMeasure {
+    /// The number of k-mers in exactly one of the files, |A| + |B| - (2 * |A & B|)
+    Hamming,
+    /// One minus the jaccard index, 1 - (|A & B| / |A | B|)
+    Jaccard,
+    /// The mash distance, an estimate of the mutation rate between the files
+    Mash,
+}
+
+/// The text formats distances can be exported to (and imported from)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TextFormat {
+    /// A square (relaxed) PHYLIP distance matrix, with a name and the distances on each line
+    Phylip,
+    /// One 'file_1, file_2, distance' line (tab separated) for every pair of files
+    Tsv,
+}
+
+impl TextFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TextFormat::Phylip => "phy",
+            TextFormat::Tsv => "tsv",
+        }
+    }
+}
+
+impl PairwiseDistances {
+    /// The distance between two files in the given measure.
+    /// Panics if the measure needs k-mer counts (or the k-mer length) and they were not recorded.
+    pub fn measure(&self, index_1: usize, index_2: usize, measure: DistanceMeasure) -> f64 {
+        let hamming = if index_1 < index_2 {
+            self.distances[index_2][index_1]
+        } else {
+            self.distances[index_1][index_2]
+        } as f64;
+        if measure == DistanceMeasure::Hamming {
+            return hamming;
+        }
+
+        let kmer_counts = self.kmer_counts.as_ref().unwrap_or_else(|| {
+            panic!("the distances do not record the number of k-mers of each file, so they can not be normalized. Compute them again with musk-pairwise-distances")
+        });
+        // |A | B| = (|A| + |B| + hamming) / 2, so 1 - J = hamming / |A | B|
+        let union_size = (kmer_counts[index_1] + kmer_counts[index_2]) as f64 + hamming;
+        let jaccard_distance = if union_size == 0.0 {
+            0.0
+        } else {
+            2.0 * hamming / union_size
+        };
+        match measure {
+            DistanceMeasure::Hamming => unreachable!(),
+            DistanceMeasure::Jaccard => jaccard_distance,
+            DistanceMeasure::Mash => {
+                let kmer_len = self.kmer_len.unwrap_or_else(|| {
+                    panic!("the distances do not record their k-mer length, which the mash distance needs")
+                });
+                let jaccard = 1.0 - jaccard_distance;
+                if jaccard == 0.0 {
+                    1.0
+                } else if jaccard == 1.0 {
+                    0.0
+                } else {
+                    -(2.0 * jaccard / (1.0 + jaccard)).ln() / kmer_len as f64
+                }
+            }
+        }
+    }
+
+    /// Writes the distances (in the given measure) as text, naming each file by its file2taxid entry
+    pub fn write_text<W: Write>(
+        &self,
+        writer: &mut W,
+        format: TextFormat,
+        measure: DistanceMeasure,
+    ) -> io::Result<()> {
+        let distance = |index_1: usize, index_2: usize| match measure {
+            DistanceMeasure::Hamming => self.measure(index_1, index_2, measure).to_string(),
+            _ => format!("{:.6}", self.measure(index_1, index_2, measure)),
+        };
+
+        match format {
+            TextFormat::Phylip => {
+                writeln!(writer, "{}", self.file2taxid.len())?;
+                for (index_1, (files, _taxid)) in self.file2taxid.iter().enumerate() {
+                    write!(writer, "{}", files)?;
+                    for index_2 in 0..self.file2taxid.len() {
+                        write!(writer, " {}", distance(index_1, index_2))?;
+                    }
+                    writeln!(writer)?;
+                }
+            }
+            TextFormat::Tsv => {
+                writeln!(writer, "file_1\tfile_2\tdistance")?;
+                for (index_1, (files_1, _taxid)) in self.file2taxid.iter().enumerate() {
+                    for (index_2, (files_2, _taxid)) in
+                        self.file2taxid[..index_1].iter().enumerate()
+                    {
+                        writeln!(
+                            writer,
+                            "{}\t{}\t{}",
+                            files_1,
+                            files_2,
+                            distance(index_1, index_2)
+                        )?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the number of k-mers of each file as '<file>\t<number of k-mers>' lines, which lets
+    /// exported jaccard and mash distances be imported again
+    pub fn write_kmer_counts<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let kmer_counts = self.kmer_counts.as_ref().unwrap_or_else(|| {
+            panic!("the distances do not record the number of k-mers of each file. Compute them again with musk-pairwise-distances")
+        });
+        for ((files, _taxid), kmer_count) in self.file2taxid.iter().zip(kmer_counts) {
+            writeln!(writer, "{}\t{}", files, kmer_count)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the number of k-mers of each file (see `write_kmer_counts`) in file2taxid order
+    pub fn read_kmer_counts<R: BufRead>(
+        reader: R,
+        file2taxid: &[(String, usize)],
+    ) -> Result<Vec<u64>, String> {
+        let indices = file_indices(file2taxid)?;
+        let mut kmer_counts = vec![None; file2taxid.len()];
+        for line in reader.lines() {
+            let line = line.map_err(|e| e.to_string())?;
+            match line.split('\t').collect::<Vec<&str>>()[..] {
+                [files, kmer_count] => {
+                    let kmer_count = kmer_count
+                        .parse::<u64>()
+                        .map_err(|_| format!("'{}' is not a number of k-mers", kmer_count))?;
+                    if let Some(index) = indices.get(files) {
+                        kmer_counts[*index] = Some(kmer_count);
+                    }
+                }
+                _ => return Err(format!("'{}' does not have 2 tab separated columns", line)),
+            }
+        }
+
+        kmer_counts
+            .into_iter()
+            .zip(file2taxid)
+            .map(|(kmer_count, (files, _taxid))| {
+                kmer_count.ok_or_else(|| format!("there is no number of k-mers for '{}'", files))
+            })
+            .collect()
+    }
+
+    /// Reads distances written as text in the given measure, ordering (and naming) the files by
+    /// the file2taxid. Every pair of files in the file2taxid must have a distance.
+    /// Jaccard and mash distances are converted back to hamming distances, which needs the number
+    /// of k-mers of each file (and the k-mer length for mash distances). As they are only written
+    /// with 6 decimals, the converted distances are close to but not exactly the original ones.
+    pub fn read_text<R: BufRead>(
+        reader: R,
+        format: TextFormat,
+        measure: DistanceMeasure,
+        file2taxid: Vec<(String, usize)>,
+        kmer_counts: Option<Vec<u64>>,
+        kmer_len: Option<usize>,
+        canonical: bool,
+    ) -> Result<Self, String> {
+        let indices = file_indices(&file2taxid)?;
+        if measure != DistanceMeasure::Hamming && kmer_counts.is_none() {
+            return Err(format!(
+                "importing {:?} distances needs the number of k-mers of each file",
+                measure
+            ));
+        }
+        if measure == DistanceMeasure::Mash && kmer_len.is_none() {
+            return Err("importing mash distances needs the k-mer length".to_string());
+        }
+        let mut distances = (0..file2taxid.len())
+            .map(|index| vec![None; index + 1])
+            .collect::<Vec<Vec<Option<u32>>>>();
+        let mut unknown_names = 0;
+        let mut set_distance = |name_1: &str, name_2: &str, distance: &str| match (
+            indices.get(name_1),
+            indices.get(name_2),
+        ) {
+            (Some(index_1), Some(index_2)) => {
+                let distance = match (measure, &kmer_counts) {
+                    (DistanceMeasure::Hamming, _) => distance.parse::<u32>().map_err(|_| {
+                        format!(
+                            "'{}' is not a hamming distance, only whole numbers can be imported",
+                            distance
+                        )
+                    })?,
+                    (_, Some(kmer_counts)) => hamming_distance(
+                        distance,
+                        measure,
+                        kmer_counts[*index_1] + kmer_counts[*index_2],
+                        kmer_len,
+                    )?,
+                    (_, None) => unreachable!(),
+                };
+                let (row, column) = (*index_1.max(index_2), *index_1.min(index_2));
+                match distances[row][column] {
+                    Some(previous) if previous != distance => Err(format!(
+                        "'{}' and '{}' have two different distances ({} and {})",
+                        name_1, name_2, previous, distance
+                    )),
+                    _ => {
+                        distances[row][column] = Some(distance);
+                        Ok(())
+                    }
+                }
+            }
+            _ => {
+                unknown_names += 1;
+                Ok(())
+            }
+        };
+
+        let mut lines = reader.lines();
+        match format {
+            TextFormat::Phylip => {
+                // Rows may wrap over several lines, so read the matrix as whitespace separated words
+                let mut words = vec![];
+                for line in lines {
+                    let line = line.map_err(|e| e.to_string())?;
+                    words.extend(line.split_whitespace().map(String::from));
+                }
+                let mut words = words.into_iter();
+                let num_files = words
+                    .next()
+                    .and_then(|word| word.parse::<usize>().ok())
+                    .ok_or("the PHYLIP matrix does not start with the number of files")?;
+                let rows = (0..num_files)
+                    .map(|_| {
+                        let name = words.next()?;
+                        let row = words.by_ref().take(num_files).collect::<Vec<String>>();
+                        (row.len() == num_files).then_some((name, row))
+                    })
+                    .collect::<Option<Vec<(String, Vec<String>)>>>()
+                    .ok_or("the PHYLIP matrix has fewer distances than files")?;
+                for (name_1, row) in rows.iter() {
+                    for ((name_2, _row), distance) in rows.iter().zip(row) {
+                        set_distance(name_1, name_2, distance)?;
+                    }
+                }
+            }
+            TextFormat::Tsv => {
+                // Skip the header
+                lines.next();
+                for line in lines {
+                    let line = line.map_err(|e| e.to_string())?;
+                    match line.split('\t').collect::<Vec<&str>>()[..] {
+                        [name_1, name_2, distance] => set_distance(name_1, name_2, distance)?,
+                        _ => {
+                            return Err(format!("'{}' does not have 3 tab separated columns", line))
+                        }
+                    }
+                }
+            }
+        }
+        if unknown_names > 0 {
+            warn!(
+                "{} distances were between files not in the file2taxid, they were ignored",
+                unknown_names
+            );
+        }
+
+        let distances = distances
+            .into_iter()
+            .enumerate()
+            .map(|(index_1, row)| {
+                row.into_iter()
+                    .enumerate()
+                    .map(|(index_2, distance)| match distance {
+                        Some(distance) => Ok(distance),
+                        None if index_1 == index_2 => Ok(0),
+                        None => Err(format!(
+                            "there is no distance between '{}' and '{}'",
+                            file2taxid[index_1].0, file2taxid[index_2].0
+                        )),
+                    })
+                    .collect::<Result<Vec<u32>, String>>()
+            })
+            .collect::<Result<Vec<Vec<u32>>, String>>()?;
+
+        Ok(PairwiseDistances {
+            kmer_len,
+            canonical,
+            distances,
+            file2taxid,
+            kmer_counts,
+        })
+    }
+}
+
+// The index of each file2taxid entry, which must be unique
+fn file_indices(file2taxid: &[(String, usize)]) -> Result<HashMap<&str, usize>, String> {
+    let mut indices = HashMap::with_capacity(file2taxid.len());
+    for (index, (files, _taxid)) in file2taxid.iter().enumerate() {
+        if indices.insert(files.as_str(), index).is_some() {
+            return Err(format!("'{}' is in the file2taxid more than once", files));
+        }
+    }
+    Ok(indices)
+}
+
+// Converts a jaccard or mash distance between two files with `total_kmers` k-mers (|A| + |B|)
+// back to the hamming distance
+fn hamming_distance(
+    distance: &str,
+    measure: DistanceMeasure,
+    total_kmers: u64,
+    kmer_len: Option<usize>,
+) -> Result<u32, String> {
+    let value = distance
+        .parse::<f64>()
+        .ok()
+        .filter(|value| value.is_finite() && *value >= 0.0)
+        .ok_or_else(|| format!("'{}' is not a {:?} distance", distance, measure))?;
+    let jaccard_distance = match measure {
+        DistanceMeasure::Hamming => unreachable!(),
+        DistanceMeasure::Jaccard if value <= 1.0 => value,
+        DistanceMeasure::Jaccard => {
+            return Err(format!("'{}' is not a {:?} distance", distance, measure))
+        }
+        // Files that share no k-mers are written with a mash distance of 1
+        DistanceMeasure::Mash if value >= 1.0 => 1.0,
+        DistanceMeasure::Mash => {
+            // mash = -ln(2J / (1 + J)) / k, so J = e / (2 - e) where e = exp(-k * mash)
+            let e = (-(kmer_len.unwrap() as f64) * value).exp();
+            1.0 - e / (2.0 - e)
+        }
+    };
+    // 1 - J = 2 * hamming / (|A| + |B| + hamming)
+    let hamming = jaccard_distance * total_kmers as f64 / (2.0 - jaccard_distance);
+    Ok(hamming.round() as u32)
+}
+
+fn canonical_str(canonical: bool) -> &'static str {
+    if canonical {
+        "canonical"
+    } else {
+        "non-canonical"
+    }
+}
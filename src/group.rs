@@ -1,11 +1,11 @@
 use indicatif::ParallelProgressIterator;
 use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator};
 use rayon::prelude::*;
-use roaring::RoaringBitmap;
+use roaring::RoaringTreemap;
 use std::collections::{HashSet, VecDeque};
 
 pub fn connected_components(
-    bitmaps: Vec<RoaringBitmap>,
+    bitmaps: Vec<RoaringTreemap>,
     minimum_similarity: f64,
 ) -> Vec<Vec<usize>> {
     let graph = create_graph(bitmaps);
@@ -13,7 +13,7 @@ pub fn connected_components(
     components
 }
 
-fn create_graph(bitmaps: Vec<RoaringBitmap>) -> Vec<Vec<f64>> {
+fn create_graph(bitmaps: Vec<RoaringTreemap>) -> Vec<Vec<f64>> {
     bitmaps
         .par_iter()
         .progress()
@@ -1,24 +1,241 @@
 use itertools::Itertools;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::any::type_name;
+use std::fmt::{self, Display};
 use std::fs::File;
-use std::io::BufWriter;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
 use std::io::{BufRead, BufReader};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use tracing::{error, info, warn};
 
+/// The first bytes of every file written by musk
+pub const MAGIC: [u8; 4] = *b"MUSK";
+
+/// The current version of the musk file format.
+/// Version 0 refers to files written before headers were added (plain bincode).
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The kind of data stored in a musk file
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Database,
+    LossyDatabase,
+    PairwiseDistances,
+    MappedDatabase,
+    KmerSample,
+    NeighborDistances,
+}
+
+impl FileKind {
+    /// The extension musk uses for this kind of file
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FileKind::Database => "db",
+            FileKind::LossyDatabase => "cdb",
+            FileKind::PairwiseDistances => "pd",
+            FileKind::MappedDatabase => "mdb",
+            FileKind::KmerSample => "ks",
+            FileKind::NeighborDistances => "knn",
+        }
+    }
+
+    /// Guesses the kind of a file from its extension (used for files without a header)
+    pub fn from_path(path: &Path) -> Option<FileKind> {
+        match path.extension()?.to_str()? {
+            "db" => Some(FileKind::Database),
+            "cdb" => Some(FileKind::LossyDatabase),
+            "pd" => Some(FileKind::PairwiseDistances),
+            "mdb" => Some(FileKind::MappedDatabase),
+            "ks" => Some(FileKind::KmerSample),
+            "knn" => Some(FileKind::NeighborDistances),
+            _ => None,
+        }
+    }
+}
+
+impl Display for FileKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FileKind::Database => "database",
+            FileKind::LossyDatabase => "lossy database",
+            FileKind::PairwiseDistances => "pairwise distances",
+            FileKind::MappedDatabase => "mapped database",
+            FileKind::KmerSample => "k-mer sample",
+            FileKind::NeighborDistances => "nearest neighbor distances",
+        };
+        write!(f, "{} (.{})", name, self.extension())
+    }
+}
+
+/// The fixed size header at the start of every musk file
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileHeader {
+    pub magic: [u8; 4],
+    pub version: u32,
+    pub kind: FileKind,
+    /// CRC32 of the serialized data following the header
+    pub checksum: u32,
+    /// The number of bytes of serialized data following the header
+    pub data_len: u64,
+}
+
+impl FileHeader {
+    pub(crate) fn new(kind: FileKind, checksum: u32, data_len: u64) -> Self {
+        FileHeader {
+            magic: MAGIC,
+            version: FORMAT_VERSION,
+            kind,
+            checksum,
+            data_len,
+        }
+    }
+}
+
+/// Data that can be written to (and loaded from) a musk file
+pub trait MuskFile: Serialize + DeserializeOwned {
+    /// The kinds of files this data can be loaded from
+    const KINDS: &'static [FileKind];
+
+    /// Deserializes data written by an older version of the file format.
+    /// This is where conversions should go when the serialized layout of a type changes.
+    /// By default, the layout is assumed to be the same as the current version.
+    fn migrate<R: Read>(version: u32, reader: R) -> bincode::Result<Self> {
+        let _ = version;
+        bincode::deserialize_from(reader)
+    }
+}
+
+/// The reasons a musk file could not be loaded
+#[derive(Debug)]
+pub enum FileError {
+    Io(io::Error),
+    MissingHeader,
+    NewerVersion {
+        found: u32,
+    },
+    WrongKind {
+        found: FileKind,
+        expected: &'static [FileKind],
+    },
+    ChecksumMismatch,
+    Deserialize(bincode::Error),
+}
+
+impl Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileError::Io(e) => write!(f, "{}", e),
+            FileError::MissingHeader => write!(f, "file does not start with a musk header"),
+            FileError::NewerVersion { found } => write!(
+                f,
+                "file format version {} is newer than the supported version {}, please update musk",
+                found, FORMAT_VERSION
+            ),
+            FileError::WrongKind { found, expected } => write!(
+                f,
+                "file is a {} but one of [{}] was expected",
+                found,
+                expected.iter().join(", ")
+            ),
+            FileError::ChecksumMismatch => {
+                write!(
+                    f,
+                    "checksum does not match, the file is corrupt or truncated"
+                )
+            }
+            FileError::Deserialize(e) => write!(f, "failed to deserialize data: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for FileError {}
+
+impl From<io::Error> for FileError {
+    fn from(e: io::Error) -> Self {
+        FileError::Io(e)
+    }
+}
+
+impl From<bincode::Error> for FileError {
+    fn from(e: bincode::Error) -> Self {
+        FileError::Deserialize(e)
+    }
+}
+
+// Computes the checksum and length of everything written through it
+struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+    len: u64,
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Computes the checksum and length of everything read through it
+struct ChecksumReader<R: Read> {
+    inner: R,
+    hasher: crc32fast::Hasher,
+    len: u64,
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.len += read as u64;
+        Ok(read)
+    }
+}
+
 pub fn create_output_file(path: &Path, extension: &str) -> File {
-    let file_path = if path.is_dir() {
-        path.join(extension)
-    } else {
-        path.with_extension(extension)
-    };
+    let file_path = output_file_path(path, extension);
 
     info!("creating output file {:?}", file_path);
 
     File::create(file_path).expect("could not create output file")
 }
 
+/// Creates the output file like `create_output_file`, but panics if it is one of the input files,
+/// which would be emptied before they are read
+pub fn create_separate_output_file(path: &Path, extension: &str, inputs: &[&Path]) -> File {
+    let file_path = output_file_path(path, extension);
+    if let Some(input) = inputs.iter().find(|input| is_same_file(&file_path, input)) {
+        panic!(
+            "the output file {:?} is the input file {:?}, provide another output location",
+            file_path, input
+        );
+    }
+
+    create_output_file(path, extension)
+}
+
+fn output_file_path(path: &Path, extension: &str) -> PathBuf {
+    if path.is_dir() {
+        path.join(extension)
+    } else {
+        path.with_extension(extension)
+    }
+}
+
+// Whether both paths resolve to the same existing file
+fn is_same_file(path_1: &Path, path_2: &Path) -> bool {
+    match (path_1.canonicalize(), path_2.canonicalize()) {
+        (Ok(path_1), Ok(path_2)) => path_1 == path_2,
+        _ => false,
+    }
+}
+
 pub fn split_string_to_taxid(line: String) -> Result<(String, usize), String> {
     let split_line = line.split("\t").collect::<Vec<&str>>();
     let file = split_line[0].to_string();
@@ -78,20 +295,100 @@ pub fn load_string2taxid(string2taxid: &Path) -> Vec<(String, usize)> {
 // Takes a file (already opened) as an input
 // All binaries open files at the start of execution, if needed.
 // All such binaries should error early in execution if an improper path is provided.
-pub fn dump_data_to_file<T: Serialize>(data: &T, file: File) -> bincode::Result<()> {
-    let buf_writer = BufWriter::new(file);
-    bincode::serialize_into(buf_writer, data)
+pub fn dump_data_to_file<T: MuskFile>(data: &T, kind: FileKind, file: File) -> bincode::Result<()> {
+    debug_assert!(T::KINDS.contains(&kind));
+    let mut buf_writer = BufWriter::new(file);
+
+    // Reserve space for the header, it is rewritten once the checksum of the data is known
+    bincode::serialize_into(&mut buf_writer, &FileHeader::new(kind, 0, 0))?;
+
+    let mut checksum_writer = ChecksumWriter {
+        inner: &mut buf_writer,
+        hasher: crc32fast::Hasher::new(),
+        len: 0,
+    };
+    bincode::serialize_into(&mut checksum_writer, data)?;
+    let header = FileHeader::new(kind, checksum_writer.hasher.finalize(), checksum_writer.len);
+
+    buf_writer.seek(SeekFrom::Start(0))?;
+    bincode::serialize_into(&mut buf_writer, &header)?;
+    buf_writer.flush()?;
+    Ok(())
+}
+
+/// Reads and validates the header of a musk file.
+/// Returns `None` if the file does not start with a header (written by an older version of musk).
+pub fn read_file_header<R: BufRead>(reader: &mut R) -> Result<Option<FileHeader>, FileError> {
+    if !reader.fill_buf()?.starts_with(&MAGIC) {
+        return Ok(None);
+    }
+    let header: FileHeader = bincode::deserialize_from(reader)?;
+    if header.version > FORMAT_VERSION {
+        return Err(FileError::NewerVersion {
+            found: header.version,
+        });
+    }
+    Ok(Some(header))
+}
+
+/// Loads data from a musk file, validating the header and checksum.
+/// Files without a header are loaded with `T::migrate` as version 0.
+pub fn try_load_data_from_file<T: MuskFile>(path: &Path) -> Result<T, FileError> {
+    let mut buf_reader = BufReader::new(File::open(path)?);
+
+    let header = match read_file_header(&mut buf_reader)? {
+        Some(header) => header,
+        None => {
+            warn!(
+                "{:?} does not have a header, it was likely written by an older version of musk",
+                path
+            );
+            warn!(
+                "it will be loaded as {}, use musk-migrate to add a header",
+                T::KINDS[0]
+            );
+            return Ok(T::migrate(0, buf_reader)?);
+        }
+    };
+
+    if !T::KINDS.contains(&header.kind) {
+        return Err(FileError::WrongKind {
+            found: header.kind,
+            expected: T::KINDS,
+        });
+    }
+
+    let mut checksum_reader = ChecksumReader {
+        inner: buf_reader,
+        hasher: crc32fast::Hasher::new(),
+        len: 0,
+    };
+    let data = if header.version == FORMAT_VERSION {
+        bincode::deserialize_from(&mut checksum_reader)
+    } else {
+        T::migrate(header.version, &mut checksum_reader)
+    };
+
+    // A truncated or modified file should be reported as such rather than as a deserialize error
+    if checksum_reader.len != header.data_len
+        || checksum_reader.hasher.finalize() != header.checksum
+    {
+        return Err(FileError::ChecksumMismatch);
+    }
+
+    Ok(data?)
 }
 
 // Takes a path (not opened) as an input
 // All binaries that need to load data will do so at the start of execution.
 // All such binaries will error here if an improper path is provided.
-pub fn load_data_from_file<T: for<'a> Deserialize<'a>>(path: &Path) -> T {
-    let buf_reader =
-        BufReader::new(File::open(path).expect(&*format!("could not open file at {:?}", path)));
-    bincode::deserialize_from(buf_reader).expect(&*format!(
-        "failed to deserialize data at {:?} into {}",
-        path,
-        type_name::<T>()
-    ))
+pub fn load_data_from_file<T: MuskFile>(path: &Path) -> T {
+    try_load_data_from_file(path).unwrap_or_else(|e| {
+        panic!(
+            "failed to load {} from file at {:?}: {}",
+            type_name::<T>(),
+            path,
+            e
+        )
+    })
 }
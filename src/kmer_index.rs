@@ -0,0 +1,114 @@
+use clap::ValueEnum;
+use itertools::Either;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::kmer_iter::MAX_U32_KMER_LEN;
+
+// Marks kmers that are not in a dense index
+const MISSING: u32 = u32::MAX;
+
+/// The largest kmer length a dense index can be created for (4^16 entries, 16 GiB)
+pub const MAX_DENSE_KMER_LEN: usize = 16;
+
+/// How kmers are mapped to their run length encodings
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum IndexKind {
+    /// Only stores the kmers in the database. Uses less memory for sparse kmer spaces
+    HashMap,
+    /// One entry for every possible kmer. Faster lookups and less memory for dense kmer spaces
+    Dense,
+}
+
+/// Maps each kmer in a database to the index of its run length encoding
+#[derive(Serialize, Deserialize)]
+pub enum KmerIndex {
+    HashMap(HashMap<u32, u32>),
+    /// The rle index of every possible kmer, `MISSING` if the kmer is not in the database
+    Dense(Box<[u32]>),
+    /// A hashmap index for kmer lengths above 16
+    HashMap64(HashMap<u64, u32>),
+}
+
+impl KmerIndex {
+    /// Creates an index where the kmer at position i in `kmers` maps to rle index i
+    pub fn from_kmers<I: ExactSizeIterator<Item = u64>>(
+        index_kind: IndexKind,
+        kmer_len: usize,
+        kmers: I,
+    ) -> Self {
+        match index_kind {
+            IndexKind::HashMap if kmer_len > MAX_U32_KMER_LEN => {
+                let mut kmer_to_rle_index = HashMap::with_capacity(kmers.len());
+                for (rle_index, kmer) in kmers.enumerate() {
+                    kmer_to_rle_index.insert(kmer, rle_index as u32);
+                }
+                KmerIndex::HashMap64(kmer_to_rle_index)
+            }
+            IndexKind::HashMap => {
+                let mut kmer_to_rle_index = HashMap::with_capacity(kmers.len());
+                for (rle_index, kmer) in kmers.enumerate() {
+                    kmer_to_rle_index.insert(kmer as u32, rle_index as u32);
+                }
+                KmerIndex::HashMap(kmer_to_rle_index)
+            }
+            IndexKind::Dense => {
+                if kmer_len > MAX_DENSE_KMER_LEN {
+                    panic!(
+                        "a dense index can only be used with a kmer length of at most {}",
+                        MAX_DENSE_KMER_LEN
+                    );
+                }
+                let mut kmer_to_rle_index = vec![MISSING; 4_usize.pow(kmer_len as u32)];
+                for (rle_index, kmer) in kmers.enumerate() {
+                    kmer_to_rle_index[kmer as usize] = rle_index as u32;
+                }
+                KmerIndex::Dense(kmer_to_rle_index.into_boxed_slice())
+            }
+        }
+    }
+
+    pub fn kind(&self) -> IndexKind {
+        match self {
+            KmerIndex::HashMap(_) | KmerIndex::HashMap64(_) => IndexKind::HashMap,
+            KmerIndex::Dense(_) => IndexKind::Dense,
+        }
+    }
+
+    /// Returns the rle index of the kmer, if it is in the database.
+    /// The kmer must be of the length the index was created for.
+    #[inline]
+    pub fn get(&self, kmer: u64) -> Option<u32> {
+        match self {
+            KmerIndex::HashMap(kmer_to_rle_index) => kmer_to_rle_index.get(&(kmer as u32)).copied(),
+            KmerIndex::Dense(kmer_to_rle_index) => match kmer_to_rle_index[kmer as usize] {
+                MISSING => None,
+                rle_index => Some(rle_index),
+            },
+            KmerIndex::HashMap64(kmer_to_rle_index) => kmer_to_rle_index.get(&kmer).copied(),
+        }
+    }
+
+    /// Iterates over each (kmer, rle index) pair in the index (in no particular order)
+    pub fn iter(&self) -> impl Iterator<Item = (u64, u32)> + '_ {
+        match self {
+            KmerIndex::HashMap(kmer_to_rle_index) => Either::Left(Either::Left(
+                kmer_to_rle_index
+                    .iter()
+                    .map(|(kmer, rle_index)| (*kmer as u64, *rle_index)),
+            )),
+            KmerIndex::Dense(kmer_to_rle_index) => Either::Left(Either::Right(
+                kmer_to_rle_index
+                    .iter()
+                    .enumerate()
+                    .filter(|(_kmer, rle_index)| **rle_index != MISSING)
+                    .map(|(kmer, rle_index)| (kmer as u64, *rle_index)),
+            )),
+            KmerIndex::HashMap64(kmer_to_rle_index) => Either::Right(
+                kmer_to_rle_index
+                    .iter()
+                    .map(|(kmer, rle_index)| (*kmer, *rle_index)),
+            ),
+        }
+    }
+}
@@ -1,9 +1,19 @@
+use itertools::Either;
+use serde::{Deserialize, Serialize};
 use std::cmp::min;
+use std::fmt::{self, Display};
 use std::slice::Iter;
+use std::str::FromStr;
 
 const COMPLEMENT: [usize; 4] = [3, 2, 1, 0];
 
-fn base2int(base: u8) -> Option<usize> {
+/// The largest supported kmer length (kmers are packed into 2 bits per nucleotide)
+pub const MAX_KMER_LEN: usize = 31;
+
+/// The largest kmer length where kmers fit in a u32
+pub(crate) const MAX_U32_KMER_LEN: usize = 16;
+
+pub(crate) fn base2int(base: u8) -> Option<usize> {
     match base {
         b'A' => Some(0),
         b'a' => Some(0),
@@ -30,6 +40,12 @@ pub struct KmerIter<'a> {
 
 impl<'a> KmerIter<'a> {
     pub fn from(sequence: &'a [u8], kmer_length: usize, canonical: bool) -> Self {
+        assert!(
+            kmer_length <= MAX_KMER_LEN,
+            "kmer length {} is larger than the maximum of {}",
+            kmer_length,
+            MAX_KMER_LEN
+        );
         KmerIter {
             canonical,
             char_iter: sequence.iter(),
@@ -134,3 +150,137 @@ impl<'a> Iterator for KmerIter<'a> {
         }
     }
 }
+
+/// A spaced seed mask (e.g. 1101101101), where only the positions marked '1' are part of a kmer.
+/// The span is the length of the mask and the weight (the length of the kmers) is the number of 1s.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SpacedSeed {
+    mask: String,
+}
+
+impl FromStr for SpacedSeed {
+    type Err = String;
+
+    fn from_str(mask: &str) -> Result<Self, Self::Err> {
+        if mask.is_empty() || mask.chars().any(|c| c != '0' && c != '1') {
+            return Err(format!(
+                "spaced seed '{}' must only contain 0s and 1s",
+                mask
+            ));
+        }
+        if !mask.starts_with('1') || !mask.ends_with('1') {
+            return Err(format!(
+                "spaced seed '{}' must start and end with a 1",
+                mask
+            ));
+        }
+        if mask.len() > MAX_KMER_LEN {
+            return Err(format!(
+                "spaced seed '{}' is longer than the maximum of {}",
+                mask, MAX_KMER_LEN
+            ));
+        }
+        Ok(SpacedSeed {
+            mask: mask.to_string(),
+        })
+    }
+}
+
+impl Display for SpacedSeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.mask)
+    }
+}
+
+impl SpacedSeed {
+    /// The number of nucleotides covered by the seed
+    pub fn span(&self) -> usize {
+        self.mask.len()
+    }
+
+    /// The number of nucleotides in each kmer
+    pub fn weight(&self) -> usize {
+        self.mask.chars().filter(|c| *c == '1').count()
+    }
+
+    // The (shift, bit mask, length in bits) of each run of 1s in the mask, from left to right
+    fn runs(&self) -> Vec<(usize, usize, usize)> {
+        let mut runs = vec![];
+        let mut start = None;
+        for (position, c) in self.mask.chars().chain(['0']).enumerate() {
+            match (c, start) {
+                ('1', None) => start = Some(position),
+                ('0', Some(run_start)) => {
+                    let run_len = position - run_start;
+                    runs.push((
+                        (self.span() - position) * 2,
+                        (1 << (run_len * 2)) - 1,
+                        run_len * 2,
+                    ));
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        runs
+    }
+}
+
+/// Iterates over the spaced kmers of a sequence.
+/// Each window the length of the seed's span is read, and only the positions in the mask are kept.
+pub struct SpacedKmerIter<'a> {
+    canonical: bool,
+    // (shift, bit mask, length in bits) of each run of 1s in the mask
+    runs: Vec<(usize, usize, usize)>,
+    window_iter: KmerIter<'a>,
+}
+
+impl<'a> SpacedKmerIter<'a> {
+    pub fn from(sequence: &'a [u8], spaced_seed: &SpacedSeed, canonical: bool) -> Self {
+        SpacedKmerIter {
+            canonical,
+            runs: spaced_seed.runs(),
+            window_iter: KmerIter::from(sequence, spaced_seed.span(), false),
+        }
+    }
+
+    fn apply_mask(&self, window: usize) -> usize {
+        self.runs
+            .iter()
+            .fold(0, |kmer, (shift, bit_mask, run_bits)| {
+                (kmer << run_bits) | ((window >> shift) & bit_mask)
+            })
+    }
+}
+
+impl<'a> Iterator for SpacedKmerIter<'a> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.window_iter.next()?;
+        // The window iterator keeps both strands up to date incrementally
+        let (window, rev_comp_window) = self.window_iter.get_curr_kmers();
+        if self.canonical {
+            Some(min(
+                self.apply_mask(window),
+                self.apply_mask(rev_comp_window),
+            ))
+        } else {
+            Some(self.apply_mask(window))
+        }
+    }
+}
+
+/// Iterates over the spaced kmers of a sequence if a spaced seed is given,
+/// otherwise over the (contiguous) kmers of length `kmer_len`.
+pub fn iter_kmers<'a>(
+    sequence: &'a [u8],
+    kmer_len: usize,
+    spaced_seed: Option<&SpacedSeed>,
+    canonical: bool,
+) -> impl Iterator<Item = usize> + 'a {
+    match spaced_seed {
+        None => Either::Left(KmerIter::from(sequence, kmer_len, canonical)),
+        Some(spaced_seed) => Either::Right(SpacedKmerIter::from(sequence, spaced_seed, canonical)),
+    }
+}
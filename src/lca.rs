@@ -0,0 +1,54 @@
+use std::path::Path;
+use taxonomy::{ncbi, GeneralTaxonomy, Taxonomy};
+use tracing::warn;
+
+// Takes a path to a directory with the NCBI taxonomy dump (nodes.dmp and names.dmp)
+pub fn load_taxonomy(taxonomy_dir: &Path) -> GeneralTaxonomy {
+    ncbi::load(taxonomy_dir).unwrap_or_else(|e| {
+        panic!(
+            "could not load the taxonomy (nodes.dmp and names.dmp) at {:?}: {}",
+            taxonomy_dir, e
+        )
+    })
+}
+
+// Warns about each tax id that cannot be found in the taxonomy
+// Reads significant for these tax ids will not be resolved to a lowest common ancestor
+pub fn check_tax_ids(taxonomy: &GeneralTaxonomy, tax_ids: &[usize]) {
+    for tax_id in tax_ids {
+        if taxonomy.to_internal_index(&tax_id.to_string()).is_err() {
+            warn!("tax id {} was not found in the provided taxonomy", tax_id);
+        }
+    }
+}
+
+/// Returns the lowest common ancestor of all tax ids.
+/// If any of the tax ids are not in the taxonomy (or there are no tax ids), `None` is returned.
+pub fn lowest_common_ancestor<I: IntoIterator<Item = usize>>(
+    taxonomy: &GeneralTaxonomy,
+    tax_ids: I,
+) -> Option<usize> {
+    let mut lca_index: Option<usize> = None;
+
+    for tax_id in tax_ids {
+        // Work with internal indices to avoid string comparisons in the taxonomy
+        let index = taxonomy.to_internal_index(&tax_id.to_string()).ok()?;
+
+        lca_index = match lca_index {
+            None => Some(index),
+            Some(lca_index) if lca_index == index => Some(lca_index),
+            Some(lca_index) => Some(
+                Taxonomy::<usize>::lca(taxonomy, lca_index, index)
+                    .expect("internal index was not found in the taxonomy"),
+            ),
+        };
+    }
+
+    lca_index.map(|index| {
+        taxonomy
+            .from_internal_index(index)
+            .expect("internal index was not found in the taxonomy")
+            .parse::<usize>()
+            .expect("tax id in the taxonomy was not an integer")
+    })
+}
@@ -1,12 +1,22 @@
 pub mod big_exp_float;
 pub mod binomial_sf;
+pub mod checkpoint;
+pub mod chunked;
 pub mod consts;
 pub mod database;
 pub mod decode;
+pub mod distances;
 pub mod group;
 pub mod io;
+pub mod kmer_index;
 pub mod kmer_iter;
+pub mod lca;
+pub mod mapped;
 pub mod order;
+pub mod report;
 pub mod rle;
+pub mod sampling;
+pub mod size_estimate;
+pub mod sketch;
 pub mod tracing;
 pub mod utility;
@@ -0,0 +1,312 @@
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::mem::size_of;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::io::{read_file_header, FileError, FileHeader, FileKind};
+use crate::kmer_iter::{SpacedSeed, MAX_U32_KMER_LEN};
+use crate::sampling::Sampling;
+
+// Layout of a mapped database (.mdb) file:
+//
+// | musk file header | metadata (bincode) | kmers (u32) | offsets (u64) | blocks (u16) |
+//
+// The header checksum only covers the metadata so that loading does not read the whole file.
+// The kmers are sorted and each array starts on an ALIGNMENT byte boundary.
+// Kmers are stored as u64 if the kmer length is above MAX_U32_KMER_LEN.
+// The blocks of the kmer at position `i` are `blocks[offsets[i]..offsets[i + 1]]`.
+// All numbers are little endian.
+const ALIGNMENT: usize = 8;
+
+/// The (small) part of a database that is deserialized when loading a mapped database
+#[derive(Serialize, Deserialize)]
+pub struct MappedMetadata {
+    pub canonical: bool,
+    pub kmer_len: usize,
+    pub spaced_seed: Option<SpacedSeed>,
+    pub sampling: Sampling,
+    pub files: Vec<String>,
+    pub tax_ids: Vec<usize>,
+    pub p_values: Vec<f64>,
+    pub num_kmers: u64,
+    pub num_blocks: u64,
+}
+
+/// The run length encodings of a database, queried directly from a memory mapped file
+pub struct MappedBlocks {
+    mmap: Mmap,
+    wide_kmers: bool,
+    kmers: Range<usize>,
+    offsets: Range<usize>,
+    blocks: Range<usize>,
+}
+
+impl MappedBlocks {
+    /// The number of distinct kmers
+    pub fn len(&self) -> usize {
+        self.offsets().len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the position of the kmer, if it is in the database
+    pub fn position(&self, kmer: u64) -> Option<usize> {
+        if self.wide_kmers {
+            self.wide_kmers().binary_search(&kmer).ok()
+        } else {
+            self.kmers().binary_search(&(kmer as u32)).ok()
+        }
+    }
+
+    /// Returns the kmer at a position
+    pub fn kmer(&self, position: usize) -> u64 {
+        if self.wide_kmers {
+            self.wide_kmers()[position]
+        } else {
+            self.kmers()[position] as u64
+        }
+    }
+
+    /// Returns the raw blocks of the run length encoding at a position
+    pub fn blocks(&self, position: usize) -> &[u16] {
+        let offsets = self.offsets();
+        &self.all_blocks()[offsets[position] as usize..offsets[position + 1] as usize]
+    }
+
+    fn kmers(&self) -> &[u32] {
+        // Safe because every bit pattern is a valid integer and the arrays are aligned
+        unsafe { self.mmap[self.kmers.clone()].align_to::<u32>().1 }
+    }
+
+    fn wide_kmers(&self) -> &[u64] {
+        unsafe { self.mmap[self.kmers.clone()].align_to::<u64>().1 }
+    }
+
+    fn offsets(&self) -> &[u64] {
+        unsafe { self.mmap[self.offsets.clone()].align_to::<u64>().1 }
+    }
+
+    fn all_blocks(&self) -> &[u16] {
+        unsafe { self.mmap[self.blocks.clone()].align_to::<u16>().1 }
+    }
+}
+
+// The number of padding bytes needed to reach the next aligned position
+fn padding(position: usize) -> usize {
+    (ALIGNMENT - position % ALIGNMENT) % ALIGNMENT
+}
+
+// Writes the header and metadata of a mapped database and returns the number of bytes written
+fn write_header_and_metadata<W: Write>(
+    writer: &mut W,
+    metadata: &MappedMetadata,
+) -> io::Result<usize> {
+    let metadata_bytes = bincode::serialize(metadata).map_err(io::Error::other)?;
+    let header = FileHeader::new(
+        FileKind::MappedDatabase,
+        crc32fast::hash(&metadata_bytes),
+        metadata_bytes.len() as u64,
+    );
+    let header_bytes = bincode::serialize(&header).map_err(io::Error::other)?;
+    writer.write_all(&header_bytes)?;
+    writer.write_all(&metadata_bytes)?;
+    Ok(header_bytes.len() + metadata_bytes.len())
+}
+
+/// Writes a mapped database (.mdb) file.
+/// `kmers_and_blocks` must be sorted by kmer and agree with the counts in `metadata`.
+pub fn dump_mapped_to_file<'a, F, I>(
+    metadata: &MappedMetadata,
+    kmers_and_blocks: F,
+    file: File,
+) -> io::Result<()>
+where
+    F: Fn() -> I,
+    I: Iterator<Item = (u64, &'a [u16])>,
+{
+    let mut buf_writer = BufWriter::new(file);
+    let mut position = write_header_and_metadata(&mut buf_writer, metadata)?;
+
+    // Each array is written in its own pass over the kmers to avoid holding a copy in memory
+    buf_writer.write_all(&vec![0_u8; padding(position)])?;
+    position += padding(position);
+    for (kmer, _blocks) in kmers_and_blocks() {
+        if metadata.kmer_len > MAX_U32_KMER_LEN {
+            buf_writer.write_all(&kmer.to_le_bytes())?;
+            position += size_of::<u64>();
+        } else {
+            buf_writer.write_all(&(kmer as u32).to_le_bytes())?;
+            position += size_of::<u32>();
+        }
+    }
+
+    buf_writer.write_all(&vec![0_u8; padding(position)])?;
+    let mut offset = 0_u64;
+    buf_writer.write_all(&offset.to_le_bytes())?;
+    for (_kmer, blocks) in kmers_and_blocks() {
+        offset += blocks.len() as u64;
+        buf_writer.write_all(&offset.to_le_bytes())?;
+    }
+
+    for (_kmer, blocks) in kmers_and_blocks() {
+        for block in blocks {
+            buf_writer.write_all(&block.to_le_bytes())?;
+        }
+    }
+
+    buf_writer.flush()
+}
+
+/// Writes a mapped database (.mdb) one kmer at a time, for databases that do not fit in memory.
+/// Each array is written to its own file in a temporary directory until the database is finished.
+pub struct MappedWriter {
+    kmer_len: usize,
+    temp_paths: [PathBuf; 3],
+    kmers: BufWriter<File>,
+    offsets: BufWriter<File>,
+    blocks: BufWriter<File>,
+    num_kmers: u64,
+    num_blocks: u64,
+    last_kmer: Option<u64>,
+}
+
+impl MappedWriter {
+    pub fn new(kmer_len: usize, temp_dir: &Path) -> io::Result<Self> {
+        let temp_paths = ["kmers", "offsets", "blocks"].map(|name| temp_dir.join(name));
+        let [kmers, offsets, blocks] = [0, 1, 2].map(|i| File::create(&temp_paths[i]));
+        Ok(MappedWriter {
+            kmer_len,
+            kmers: BufWriter::new(kmers?),
+            offsets: BufWriter::new(offsets?),
+            blocks: BufWriter::new(blocks?),
+            temp_paths,
+            num_kmers: 0,
+            num_blocks: 0,
+            last_kmer: None,
+        })
+    }
+
+    /// Adds the blocks of a kmer. Kmers must be pushed in (strictly) increasing order.
+    pub fn push(&mut self, kmer: u64, blocks: &[u16]) -> io::Result<()> {
+        if self.last_kmer.is_some_and(|last_kmer| last_kmer >= kmer) {
+            panic!("kmers must be written to a mapped database in sorted order");
+        }
+        self.last_kmer = Some(kmer);
+
+        if self.kmer_len > MAX_U32_KMER_LEN {
+            self.kmers.write_all(&kmer.to_le_bytes())?;
+        } else {
+            self.kmers.write_all(&(kmer as u32).to_le_bytes())?;
+        }
+        for block in blocks {
+            self.blocks.write_all(&block.to_le_bytes())?;
+        }
+        self.num_kmers += 1;
+        self.num_blocks += blocks.len() as u64;
+        self.offsets.write_all(&self.num_blocks.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Writes the mapped database to `file` and removes the temporary files.
+    /// The kmer and block counts of `metadata` are set by the writer.
+    pub fn finish(mut self, mut metadata: MappedMetadata, file: File) -> io::Result<()> {
+        metadata.num_kmers = self.num_kmers;
+        metadata.num_blocks = self.num_blocks;
+        for writer in [&mut self.kmers, &mut self.offsets, &mut self.blocks] {
+            writer.flush()?;
+        }
+        drop((self.kmers, self.offsets, self.blocks));
+
+        let mut buf_writer = BufWriter::new(file);
+        let mut position = write_header_and_metadata(&mut buf_writer, &metadata)?;
+
+        buf_writer.write_all(&vec![0_u8; padding(position)])?;
+        position += padding(position);
+        position += io::copy(&mut File::open(&self.temp_paths[0])?, &mut buf_writer)? as usize;
+
+        buf_writer.write_all(&vec![0_u8; padding(position)])?;
+        buf_writer.write_all(&0_u64.to_le_bytes())?;
+        io::copy(&mut File::open(&self.temp_paths[1])?, &mut buf_writer)?;
+        io::copy(&mut File::open(&self.temp_paths[2])?, &mut buf_writer)?;
+        buf_writer.flush()?;
+
+        for temp_path in &self.temp_paths {
+            fs::remove_file(temp_path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Memory maps a mapped database (.mdb) file, only deserializing the metadata
+pub fn load_mapped_from_file(path: &Path) -> Result<(MappedMetadata, MappedBlocks), FileError> {
+    if cfg!(target_endian = "big") {
+        panic!("mapped databases can only be loaded on little endian machines");
+    }
+
+    let file = File::open(path)?;
+    // The file must not be modified while it is mapped
+    let mmap = unsafe { Mmap::map(&file)? };
+
+    let mut remaining = &mmap[..];
+    let header = read_file_header(&mut remaining)?.ok_or(FileError::MissingHeader)?;
+    if header.kind != FileKind::MappedDatabase {
+        return Err(FileError::WrongKind {
+            found: header.kind,
+            expected: &[FileKind::MappedDatabase],
+        });
+    }
+
+    // Validate and deserialize the metadata
+    let metadata_start = mmap.len() - remaining.len();
+    let metadata_end = metadata_start + header.data_len as usize;
+    let metadata_bytes = mmap
+        .get(metadata_start..metadata_end)
+        .ok_or(FileError::ChecksumMismatch)?;
+    if crc32fast::hash(metadata_bytes) != header.checksum {
+        return Err(FileError::ChecksumMismatch);
+    }
+    let metadata: MappedMetadata = bincode::deserialize(metadata_bytes)?;
+
+    // Find where each array is in the file
+    let wide_kmers = metadata.kmer_len > MAX_U32_KMER_LEN;
+    let kmer_size = if wide_kmers {
+        size_of::<u64>()
+    } else {
+        size_of::<u32>()
+    };
+    let kmers_start = metadata_end + padding(metadata_end);
+    let kmers_end = kmers_start + metadata.num_kmers as usize * kmer_size;
+    let offsets_start = kmers_end + padding(kmers_end);
+    let offsets_end = offsets_start + (metadata.num_kmers as usize + 1) * size_of::<u64>();
+    let blocks_end = offsets_end + metadata.num_blocks as usize * size_of::<u16>();
+    if mmap.len() != blocks_end {
+        return Err(FileError::ChecksumMismatch);
+    }
+
+    // The mmap is page aligned, so each array is aligned in memory as well
+    assert_eq!(mmap.as_ptr() as usize % ALIGNMENT, 0);
+
+    let mapped_blocks = MappedBlocks {
+        mmap,
+        wide_kmers,
+        kmers: kmers_start..kmers_end,
+        offsets: offsets_start..offsets_end,
+        blocks: offsets_end..blocks_end,
+    };
+
+    // Every run length encoding must be within the blocks, otherwise querying it would panic
+    let offsets = mapped_blocks.offsets();
+    if offsets[0] != 0
+        || offsets[metadata.num_kmers as usize] != metadata.num_blocks
+        || offsets.windows(2).any(|pair| pair[0] > pair[1])
+    {
+        return Err(FileError::ChecksumMismatch);
+    }
+
+    Ok((metadata, mapped_blocks))
+}
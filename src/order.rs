@@ -1,48 +1,354 @@
-use std::collections::HashSet;
+use clap::ValueEnum;
+use rayon::prelude::*;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 
-pub fn greedy_ordering(distances: &Vec<Vec<u32>>, start_index: usize) -> Vec<usize> {
-    let mut connected_indices = HashSet::from([start_index]);
-    let mut ordering = vec![start_index];
-    let mut current_index = start_index;
+use crate::distances::NeighborDistances;
+
+/// How the initial ordering of the files is constructed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OrderingAlgorithm {
+    /// Repeatedly move to the closest file that was not visited yet
+    Greedy,
+    /// Greedy orderings from several evenly spaced start files, keeping the shortest
+    MultiStartGreedy,
+    /// A depth first traversal of the minimum spanning tree, visiting closer files first
+    Mst,
+}
+
+/// Distances between files that an ordering can be found from.
+/// Implemented for the lower triangle of a distance matrix and for nearest neighbor distances.
+pub trait FileDistances: Sync {
+    fn num_files(&self) -> usize;
+
+    /// The distance between two files
+    fn distance(&self, file_1: usize, file_2: usize) -> u32;
+
+    /// The files (and their distances) that are considered when moving on from `file`.
+    /// This is every other file, unless the distances are sparse.
+    fn neighbors(&self, file: usize) -> impl Iterator<Item = (usize, u32)> + '_;
+
+    /// The closest neighbor of `file` that `include` is true for
+    fn closest<F: Fn(usize) -> bool>(&self, file: usize, include: F) -> Option<usize> {
+        self.neighbors(file)
+            .filter(|(neighbor, _distance)| include(*neighbor))
+            .min_by_key(|(neighbor, distance)| (*distance, *neighbor))
+            .map(|(neighbor, _distance)| neighbor)
+    }
+}
+
+impl FileDistances for Vec<Vec<u32>> {
+    fn num_files(&self) -> usize {
+        self.len()
+    }
+
+    fn distance(&self, file_1: usize, file_2: usize) -> u32 {
+        if file_1 < file_2 {
+            self[file_2][file_1]
+        } else {
+            self[file_1][file_2]
+        }
+    }
+
+    fn neighbors(&self, file: usize) -> impl Iterator<Item = (usize, u32)> + '_ {
+        (0..self.len())
+            .filter(move |other| *other != file)
+            .map(move |other| (other, self.distance(file, other)))
+    }
+}
+
+impl FileDistances for NeighborDistances {
+    fn num_files(&self) -> usize {
+        self.neighbors.len()
+    }
 
-    while ordering.len() < distances.len() {
-        let mut next_index = 0_usize;
-        let mut next_index_minimum = u32::MAX;
+    fn distance(&self, file_1: usize, file_2: usize) -> u32 {
+        if file_1 == file_2 {
+            return 0;
+        }
+        let file_neighbors = &self.neighbors[file_1];
+        file_neighbors
+            .binary_search_by_key(&(file_2 as u32), |(neighbor, _distance)| *neighbor)
+            .map_or(self.far_distance, |index| file_neighbors[index].1)
+    }
 
-        let mut distance_iter = distances[current_index]
+    fn neighbors(&self, file: usize) -> impl Iterator<Item = (usize, u32)> + '_ {
+        self.neighbors[file]
             .iter()
-            .chain(
-                distances[(current_index + 1)..]
-                    .iter()
-                    .map(|row| &row[current_index]),
-            )
-            .enumerate();
-
-        while let Some((index, distance)) = distance_iter.next() {
-            if *distance < next_index_minimum && !connected_indices.contains(&index) {
-                next_index = index;
-                next_index_minimum = *distance;
-            }
+            .map(|(neighbor, distance)| (*neighbor as usize, *distance))
+    }
+}
+
+// The distance between the files at two positions of an ordering, 0 if either is past an end
+fn edge<D: FileDistances>(
+    ordering: &[usize],
+    distances: &D,
+    position_1: Option<usize>,
+    position_2: Option<usize>,
+) -> i64 {
+    match (position_1, position_2) {
+        (Some(position_1), Some(position_2)) if position_2 < ordering.len() => {
+            distances.distance(ordering[position_1], ordering[position_2]) as i64
         }
+        _ => 0,
+    }
+}
+
+/// Orders the files by repeatedly moving to the closest file that was not visited yet.
+/// If every neighbor of a file was visited (with sparse distances), it jumps to the first file
+/// that was not visited yet.
+pub fn greedy_ordering<D: FileDistances>(distances: &D, start_index: usize) -> Vec<usize> {
+    let num_files = distances.num_files();
+    let mut visited = vec![false; num_files];
+    visited[start_index] = true;
+    let mut ordering = vec![start_index];
+    let mut current_index = start_index;
+    // Every file before this one was visited
+    let mut first_unvisited = 0;
+
+    while ordering.len() < num_files {
+        let next_index = distances
+            .closest(current_index, |index| !visited[index])
+            .unwrap_or_else(|| {
+                while visited[first_unvisited] {
+                    first_unvisited += 1;
+                }
+                first_unvisited
+            });
 
         ordering.push(next_index);
-        connected_indices.insert(next_index);
+        visited[next_index] = true;
         current_index = next_index;
     }
 
     ordering
 }
 
-pub fn ordering_statistics(ordering: &Vec<usize>, distances: &Vec<Vec<u32>>) -> (f64, u64) {
+pub fn ordering_statistics<D: FileDistances>(ordering: &[usize], distances: &D) -> (f64, u64) {
     let sum = ordering
         .windows(2)
-        .map(|x| {
-            if x[0] < x[1] {
-                distances[x[1]][x[0]] as u64
-            } else {
-                distances[x[0]][x[1]] as u64
-            }
-        })
+        .map(|x| distances.distance(x[0], x[1]) as u64)
         .sum();
     (sum as f64 / (ordering.len() - 1) as f64, sum)
 }
+
+/// Runs the greedy ordering from `num_starts` evenly spaced start files and returns the shortest
+pub fn multi_start_greedy_ordering<D: FileDistances>(
+    distances: &D,
+    num_starts: usize,
+) -> Vec<usize> {
+    let num_files = distances.num_files();
+    let num_starts = num_starts.clamp(1, num_files);
+    (0..num_starts)
+        .into_par_iter()
+        .map(|start| {
+            let ordering = greedy_ordering(distances, start * num_files / num_starts);
+            (ordering_statistics(&ordering, distances).1, ordering)
+        })
+        .min_by_key(|(total_dist, _ordering)| *total_dist)
+        .unwrap()
+        .1
+}
+
+/// Orders the files by a depth first traversal of the minimum spanning tree from `start_index`.
+/// The children of each file are visited from closest to farthest.
+/// If the files are not all connected (with sparse distances), the spanning tree of the first
+/// file that is not connected yet is traversed next.
+pub fn mst_ordering<D: FileDistances>(distances: &D, start_index: usize) -> Vec<usize> {
+    // Prim's algorithm, where each file is pushed whenever a closer tree file is found
+    // (distance, file, parent) and files already in the tree are skipped when popped
+    let num_files = distances.num_files();
+    let mut in_tree = vec![false; num_files];
+    let mut closest = vec![u32::MAX; num_files];
+    let mut children = vec![vec![]; num_files];
+    let mut roots = vec![];
+    let mut heap: BinaryHeap<Reverse<(u32, usize, Option<usize>)>> =
+        BinaryHeap::from([Reverse((0, start_index, None))]);
+    // Every file before this one is in the tree
+    let mut first_outside = 0;
+
+    for _ in 0..num_files {
+        let (dist, next_index, parent) = loop {
+            match heap.pop() {
+                Some(Reverse((_dist, index, _parent))) if in_tree[index] => {}
+                Some(Reverse(edge)) => break edge,
+                None => {
+                    while in_tree[first_outside] {
+                        first_outside += 1;
+                    }
+                    break (0, first_outside, None);
+                }
+            }
+        };
+        in_tree[next_index] = true;
+        match parent {
+            Some(parent) => children[parent].push((dist, next_index)),
+            None => roots.push(next_index),
+        }
+
+        for (index, dist) in distances.neighbors(next_index) {
+            if !in_tree[index] && dist < closest[index] {
+                closest[index] = dist;
+                heap.push(Reverse((dist, index, Some(next_index))));
+            }
+        }
+    }
+
+    let mut ordering = Vec::with_capacity(num_files);
+    for root in roots {
+        let mut stack = vec![root];
+        while let Some(index) = stack.pop() {
+            ordering.push(index);
+            // Push the farthest child first so that the closest is visited next
+            children[index].sort_unstable();
+            stack.extend(children[index].iter().rev().map(|(_dist, child)| *child));
+        }
+    }
+
+    ordering
+}
+
+// The position of each file in an ordering, `None` for files that are not in it
+fn file_positions(ordering: &[usize], num_files: usize) -> Vec<Option<usize>> {
+    let mut positions = vec![None; num_files];
+    for (position, file) in ordering.iter().enumerate() {
+        positions[*file] = Some(position);
+    }
+    positions
+}
+
+/// Improves an ordering by reversing segments of it (2-opt) until no reversal shortens it.
+/// Only reversals that place a file next to one of its neighbors are tried, so each pass takes
+/// time linear in the number of neighbors (quadratic in the number of files for full distances).
+/// Returns how much shorter the ordering became.
+pub fn two_opt<D: FileDistances>(ordering: &mut [usize], distances: &D) -> u64 {
+    let mut positions = file_positions(ordering, distances.num_files());
+    let mut total_gain = 0;
+    let mut improved = true;
+    while improved {
+        improved = false;
+        for file in 0..distances.num_files() {
+            for (neighbor, _distance) in distances.neighbors(file) {
+                let (Some(position_1), Some(position_2)) = (positions[file], positions[neighbor])
+                else {
+                    continue;
+                };
+                let (first, second) = (position_1.min(position_2), position_1.max(position_2));
+                if second <= first + 1 {
+                    continue;
+                }
+
+                // Either reversal places the two files next to each other
+                for (start, end) in [(first + 1, second), (first, second - 1)] {
+                    // Reversing start..=end replaces the edges on either side of the segment
+                    let before = start.checked_sub(1);
+                    let removed = edge(ordering, distances, before, Some(start))
+                        + edge(ordering, distances, Some(end), Some(end + 1));
+                    let added = edge(ordering, distances, before, Some(end))
+                        + edge(ordering, distances, Some(start), Some(end + 1));
+                    if added < removed {
+                        ordering[start..=end].reverse();
+                        for position in start..=end {
+                            positions[ordering[position]] = Some(position);
+                        }
+                        total_gain += (removed - added) as u64;
+                        improved = true;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    total_gain
+}
+
+/// Improves an ordering by moving segments of up to 3 files (possibly reversed) to another place
+/// in the ordering (Or-opt) until no move shortens it.
+/// A segment is only moved next to a neighbor of either of its ends, or to either end of the
+/// ordering.
+/// Returns how much shorter the ordering became.
+pub fn or_opt<D: FileDistances>(ordering: &mut Vec<usize>, distances: &D) -> u64 {
+    let mut positions = file_positions(ordering, distances.num_files());
+    let mut total_gain = 0;
+    let mut improved = true;
+    while improved {
+        improved = false;
+        for segment_len in 1..=3 {
+            let mut start = 0;
+            while start + segment_len <= ordering.len() {
+                let end = start + segment_len - 1;
+                let before = start.checked_sub(1);
+                let removed_gain = edge(ordering, distances, before, Some(start))
+                    + edge(ordering, distances, Some(end), Some(end + 1))
+                    - edge(ordering, distances, before, Some(end + 1));
+
+                // The places the segment can be inserted, between `after` and the next file:
+                // after or before a neighbor of either end, or at the back of the ordering
+                let mut candidates = vec![ordering.len() - 1];
+                for file in [ordering[start], ordering[end]] {
+                    for (neighbor, _distance) in distances.neighbors(file) {
+                        if let Some(position) = positions[neighbor] {
+                            candidates.push(position);
+                            candidates.extend(position.checked_sub(1));
+                        }
+                    }
+                }
+
+                // Find the best place to insert the segment
+                let mut best_move = None;
+                let mut best_gain = 0;
+                for after in candidates
+                    .into_iter()
+                    .filter(|after| *after + 1 < start || *after > end)
+                {
+                    let next = after + 1;
+                    for reversed in [false, true] {
+                        let (first, last) = if reversed { (end, start) } else { (start, end) };
+                        let insert_cost = edge(ordering, distances, Some(after), Some(first))
+                            + edge(ordering, distances, Some(last), Some(next))
+                            - edge(ordering, distances, Some(after), Some(next));
+                        if removed_gain - insert_cost > best_gain {
+                            best_gain = removed_gain - insert_cost;
+                            best_move = Some((Some(after), reversed));
+                        }
+                    }
+                }
+                // Moving the segment to the front of the ordering
+                if start > 0 {
+                    for reversed in [false, true] {
+                        let last = if reversed { start } else { end };
+                        let insert_cost = edge(ordering, distances, Some(last), Some(0));
+                        if removed_gain - insert_cost > best_gain {
+                            best_gain = removed_gain - insert_cost;
+                            best_move = Some((None, reversed));
+                        }
+                    }
+                }
+
+                match best_move {
+                    Some((after, reversed)) => {
+                        let mut segment = ordering.drain(start..=end).collect::<Vec<usize>>();
+                        if reversed {
+                            segment.reverse();
+                        }
+                        let insert_at = match after {
+                            None => 0,
+                            Some(after) if after > end => after + 1 - segment_len,
+                            Some(after) => after + 1,
+                        };
+                        ordering.splice(insert_at..insert_at, segment);
+                        // Only the files between the old and new place of the segment moved
+                        for position in start.min(insert_at)..(end + 1).max(insert_at + segment_len)
+                        {
+                            positions[ordering[position]] = Some(position);
+                        }
+                        total_gain += best_gain as u64;
+                        improved = true;
+                    }
+                    None => start += 1,
+                }
+            }
+        }
+    }
+    total_gain
+}
@@ -0,0 +1,164 @@
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use taxonomy::{GeneralTaxonomy, TaxRank, Taxonomy};
+use tracing::warn;
+
+/// Formats the tax id of each k-mer position like Kraken does, e.g. "562:13 561:4 A:31 0:1".
+/// Positions with an ambiguous nucleotide (`None`) are written as "A".
+pub fn kraken_kmer_mapping(kmer_tax_ids: &[Option<usize>]) -> String {
+    kmer_tax_ids
+        .iter()
+        .chunk_by(|tax_id| **tax_id)
+        .into_iter()
+        .map(|(tax_id, run)| match tax_id {
+            Some(tax_id) => format!("{}:{}", tax_id, run.count()),
+            None => format!("A:{}", run.count()),
+        })
+        .join(" ")
+}
+
+/// Creates one line of Kraken standard output.
+/// For paired reads, the lengths and k-mer mappings of both mates should be given (in order).
+pub fn kraken_line(
+    tax_id: Option<usize>,
+    read_id: &str,
+    lengths: &[usize],
+    kmer_mappings: &[String],
+) -> String {
+    let (status, tax_id) = match tax_id {
+        Some(tax_id) => ("C", tax_id),
+        None => ("U", 0),
+    };
+    format!(
+        "{}\t{}\t{}\t{}\t{}\n",
+        status,
+        read_id,
+        tax_id,
+        lengths.iter().join("|"),
+        kmer_mappings.join(" |:| ")
+    )
+}
+
+// The Kraken report code for the standard ranks
+fn rank_code(rank: TaxRank) -> Option<&'static str> {
+    match rank {
+        TaxRank::Superkingdom | TaxRank::Domain => Some("D"),
+        TaxRank::Kingdom => Some("K"),
+        TaxRank::Phylum => Some("P"),
+        TaxRank::Class => Some("C"),
+        TaxRank::Order => Some("O"),
+        TaxRank::Family => Some("F"),
+        TaxRank::Genus => Some("G"),
+        TaxRank::Species => Some("S"),
+        _ => None,
+    }
+}
+
+/// Writes a Kraken style report (kreport) of the number of reads assigned to each tax id.
+/// Only taxa with at least one read in their clade are written, along with the tax ids in
+/// `zero_count_tax_ids` (and their ancestors) so that all database taxa can be reported.
+pub fn write_kreport<W: Write>(
+    writer: &mut W,
+    taxonomy: &GeneralTaxonomy,
+    tax_id_counts: &HashMap<usize, u64>,
+    unclassified: u64,
+    zero_count_tax_ids: &[usize],
+) -> io::Result<()> {
+    // Count the reads assigned directly to each node (by internal index)
+    let mut direct_counts = vec![0_u64; Taxonomy::<usize>::len(taxonomy)];
+    let mut total = unclassified;
+    for (tax_id, count) in tax_id_counts {
+        total += *count;
+        match taxonomy.to_internal_index(&tax_id.to_string()) {
+            Ok(index) => direct_counts[index] += *count,
+            Err(_) => warn!(
+                "{} reads were assigned to tax id {} which is not in the taxonomy, leaving out of the report",
+                count, tax_id
+            ),
+        }
+    }
+
+    // Sum the counts of each clade, children are always finished before their parents
+    let root = Taxonomy::<usize>::root(taxonomy);
+    let mut clade_counts = direct_counts.clone();
+    for (index, pre_order) in Taxonomy::<usize>::traverse(taxonomy, root).unwrap() {
+        if !pre_order {
+            if let Some((parent, _)) = Taxonomy::<usize>::parent(taxonomy, index).unwrap() {
+                clade_counts[parent] += clade_counts[index];
+            }
+        }
+    }
+
+    // Decide which nodes should be reported
+    let mut included = clade_counts.iter().map(|count| *count > 0).collect_vec();
+    for tax_id in zero_count_tax_ids {
+        if let Ok(index) = taxonomy.to_internal_index(&tax_id.to_string()) {
+            for ancestor in Taxonomy::<usize>::lineage(taxonomy, index).unwrap() {
+                included[ancestor] = true;
+            }
+        }
+    }
+
+    let percent = |count: u64| {
+        if total == 0 {
+            0.0
+        } else {
+            count as f64 * 100.0 / total as f64
+        }
+    };
+
+    writeln!(
+        writer,
+        "{:6.2}\t{}\t{}\tU\t0\tunclassified",
+        percent(unclassified),
+        unclassified,
+        unclassified
+    )?;
+
+    // Write each included node depth first, visiting larger clades first like Kraken does
+    let mut stack = vec![(root, 0_usize, ("R", 0_usize))];
+    while let Some((index, depth, (parent_code, parent_offset))) = stack.pop() {
+        if !included[index] {
+            continue;
+        }
+
+        // Non-standard ranks use the closest standard rank above them with an offset (e.g. S1)
+        let (code, offset) = if index == root {
+            ("R", 0)
+        } else {
+            match rank_code(Taxonomy::<usize>::rank(taxonomy, index).unwrap()) {
+                Some(code) => (code, 0),
+                None => (parent_code, parent_offset + 1),
+            }
+        };
+        let rank_string = if offset == 0 {
+            code.to_string()
+        } else {
+            format!("{}{}", code, offset)
+        };
+
+        writeln!(
+            writer,
+            "{:6.2}\t{}\t{}\t{}\t{}\t{}{}",
+            percent(clade_counts[index]),
+            clade_counts[index],
+            direct_counts[index],
+            rank_string,
+            taxonomy.from_internal_index(index).unwrap(),
+            "  ".repeat(depth),
+            Taxonomy::<usize>::name(taxonomy, index).unwrap()
+        )?;
+
+        // Push the smallest clades first so that the largest are popped first
+        let children = Taxonomy::<usize>::children(taxonomy, index)
+            .unwrap()
+            .into_iter()
+            .sorted_by_key(|child| clade_counts[*child]);
+        for child in children {
+            stack.push((child, depth + 1, (code, offset)));
+        }
+    }
+
+    Ok(())
+}
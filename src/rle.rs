@@ -1,4 +1,5 @@
 use bit_iter::BitIter;
+use itertools::Either;
 use serde::{Deserialize, Serialize};
 use std::slice::Iter;
 use tracing::warn;
@@ -149,6 +150,16 @@ impl RunLengthEncoding {
         RunLengthEncodingBlockIter::from_blocks(&self.blocks)
     }
 
+    /// Iterates over the set indices in increasing order
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.block_iters().flat_map(|block_iter| match block_iter {
+            BlockIter::Range((start_i, end_i)) => Either::Left(start_i..end_i),
+            BlockIter::BitIter((bit_iter, start_i)) => {
+                Either::Right(bit_iter.map(move |i| i + start_i))
+            }
+        })
+    }
+
     pub fn from(blocks: Box<[u16]>) -> RunLengthEncoding {
         RunLengthEncoding { blocks }
     }
@@ -266,32 +277,34 @@ impl RunLengthEncoding {
     }
 
     pub fn collect_indices(&self) -> Vec<u32> {
-        // Create the blocks iterator
-        let mut blocks_iter = self
-            .blocks
-            .iter()
-            .map(|block_u16| Block::from_u16(*block_u16));
-
-        // Initialize curr_i and the return value
-        let mut curr_i = 0_u32;
-        let mut indices = vec![];
-
-        while let Some(block) = blocks_iter.next() {
-            match block {
-                Block::Zeros(zeroes_count) => curr_i += zeroes_count as u32,
-                Block::Ones(ones_count) => {
-                    let ones_count = ones_count as u32;
-                    indices.extend(curr_i..curr_i + ones_count);
-                    curr_i += ones_count;
-                }
-                Block::Uncompressed(bits) => {
-                    indices.extend(BitIter::from(bits).map(|i| i as u32 + curr_i));
-                    curr_i += MAX_UNCOMPRESSED_BITS as u32;
-                }
+        collect_indices(&self.blocks)
+    }
+}
+
+/// Collects the indices set in the raw blocks of a run length encoding
+pub fn collect_indices(blocks: &[u16]) -> Vec<u32> {
+    // Create the blocks iterator
+    let mut blocks_iter = blocks.iter().map(|block_u16| Block::from_u16(*block_u16));
+
+    // Initialize curr_i and the return value
+    let mut curr_i = 0_u32;
+    let mut indices = vec![];
+
+    while let Some(block) = blocks_iter.next() {
+        match block {
+            Block::Zeros(zeroes_count) => curr_i += zeroes_count as u32,
+            Block::Ones(ones_count) => {
+                let ones_count = ones_count as u32;
+                indices.extend(curr_i..curr_i + ones_count);
+                curr_i += ones_count;
+            }
+            Block::Uncompressed(bits) => {
+                indices.extend(BitIter::from(bits).map(|i| i as u32 + curr_i));
+                curr_i += MAX_UNCOMPRESSED_BITS as u32;
             }
         }
-        indices
     }
+    indices
 }
 
 // Takes a buffer of exactly MAX_UNCOMPRESSED_BITS and converts it to a bit set
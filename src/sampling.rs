@@ -0,0 +1,149 @@
+use itertools::Either;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt::{self, Display};
+
+use crate::kmer_iter::{base2int, iter_kmers, SpacedSeed};
+
+/// Which kmers of a sequence are stored in (and queried against) a database.
+/// The same sampling must be used for the references and the reads.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sampling {
+    /// Every kmer is used
+    All,
+    /// The kmer with the smallest hash in every window of `window` consecutive kmers
+    Minimizer { window: usize },
+    /// Kmers where the s-mer (of length `smer_len`) with the smallest hash is at the start
+    OpenSyncmer { smer_len: usize },
+}
+
+impl Display for Sampling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sampling::All => write!(f, "all k-mers"),
+            Sampling::Minimizer { window } => write!(f, "minimizers (window {})", window),
+            Sampling::OpenSyncmer { smer_len } => {
+                write!(f, "open syncmers (s-mer length {})", smer_len)
+            }
+        }
+    }
+}
+
+impl Sampling {
+    /// Panics if the sampling can not be used with the kmer length
+    pub fn validate(&self, kmer_len: usize) {
+        match self {
+            Sampling::All => {}
+            Sampling::Minimizer { window } => {
+                if *window == 0 {
+                    panic!("the minimizer window must be at least 1");
+                }
+            }
+            Sampling::OpenSyncmer { smer_len } => {
+                if *smer_len == 0 || *smer_len > kmer_len {
+                    panic!(
+                        "the syncmer s-mer length must be between 1 and the k-mer length ({})",
+                        kmer_len
+                    );
+                }
+            }
+        }
+    }
+
+    /// The (expected) fraction of kmers that are sampled
+    pub fn density(&self, kmer_len: usize) -> f64 {
+        match self {
+            Sampling::All => 1.0,
+            Sampling::Minimizer { window } => 2.0 / (*window as f64 + 1.0),
+            Sampling::OpenSyncmer { smer_len } => 1.0 / (kmer_len - smer_len + 1) as f64,
+        }
+    }
+
+    /// Returns the sampled kmers of a sequence (in order of their position).
+    /// If a spaced seed is given, `kmer_len` must be its weight.
+    pub fn kmers<'a>(
+        &self,
+        sequence: &'a [u8],
+        kmer_len: usize,
+        spaced_seed: Option<&SpacedSeed>,
+        canonical: bool,
+    ) -> impl Iterator<Item = u64> + 'a {
+        let kmer_iter =
+            iter_kmers(sequence, kmer_len, spaced_seed, canonical).map(|kmer| kmer as u64);
+        let span = spaced_seed.map_or(kmer_len, |spaced_seed| spaced_seed.span());
+        let spaced_seed = spaced_seed.cloned();
+        match *self {
+            Sampling::All => Either::Left(Either::Left(kmer_iter)),
+            Sampling::OpenSyncmer { smer_len } => Either::Left(Either::Right(
+                kmer_iter.filter(move |kmer| is_open_syncmer(*kmer, kmer_len, smer_len)),
+            )),
+            Sampling::Minimizer { window } => Either::Right(
+                // Windows do not span ambiguous nucleotides, so find the minimizers of each
+                // unambiguous stretch of the sequence separately
+                sequence
+                    .split(|base| base2int(*base).is_none())
+                    .filter(move |stretch| stretch.len() >= span)
+                    .flat_map(move |stretch| {
+                        let kmers = iter_kmers(stretch, kmer_len, spaced_seed.as_ref(), canonical)
+                            .map(|kmer| kmer as u64)
+                            .collect::<Vec<u64>>();
+                        window_minimizers(&kmers, window)
+                    }),
+            ),
+        }
+    }
+}
+
+// The finalizer of MurmurHash3, so that sampling is not biased towards kmers with many A's
+pub(crate) fn hash(mut x: u64) -> u64 {
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51afd7ed558ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ceb9fe1a85ec53);
+    x ^= x >> 33;
+    x
+}
+
+// Ties go to the leftmost s-mer, so a kmer is a syncmer if no s-mer hashes lower than its first
+// Only depends on the kmer itself, so canonical kmers are sampled the same on both strands
+fn is_open_syncmer(kmer: u64, kmer_len: usize, smer_len: usize) -> bool {
+    let smer_mask = (1_u64 << (2 * smer_len)) - 1;
+    let first_smer_shift = 2 * (kmer_len - smer_len);
+    let first_hash = hash(kmer >> first_smer_shift);
+    (0..first_smer_shift)
+        .step_by(2)
+        .all(|shift| hash((kmer >> shift) & smer_mask) >= first_hash)
+}
+
+// Returns the kmer with the smallest hash (leftmost if tied) in each window, skipping consecutive
+// windows that select the same position. A stretch shorter than a window is treated as one window.
+fn window_minimizers(kmers: &[u64], window: usize) -> Vec<u64> {
+    let hashes = kmers.iter().map(|kmer| hash(*kmer)).collect::<Vec<u64>>();
+    let mut minimizers = vec![];
+    let mut last_selected = None;
+
+    // Holds the positions that may still be the minimum of a window, with increasing hashes
+    let mut candidates: VecDeque<usize> = VecDeque::with_capacity(window);
+    for position in 0..hashes.len() {
+        while candidates
+            .back()
+            .is_some_and(|back| hashes[*back] > hashes[position])
+        {
+            candidates.pop_back();
+        }
+        candidates.push_back(position);
+        if candidates[0] + window <= position {
+            candidates.pop_front();
+        }
+
+        if position + 1 >= window || position + 1 == hashes.len() {
+            let selected = candidates[0];
+            if last_selected != Some(selected) {
+                minimizers.push(kmers[selected]);
+                last_selected = Some(selected);
+            }
+        }
+    }
+
+    minimizers
+}
@@ -0,0 +1,289 @@
+use rayon::prelude::*;
+use roaring::RoaringTreemap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::io::{FileKind, MuskFile};
+use crate::kmer_iter::MAX_U32_KMER_LEN;
+use crate::order::FileDistances;
+use crate::rle::NaiveRunLengthEncoding;
+use crate::sampling::hash;
+
+/// The files that contain a (hash based) sample of the k-mers of every file.
+/// Used to estimate the number of blocks a database will have for an ordering of the files.
+#[derive(Serialize, Deserialize)]
+pub struct KmerSample {
+    pub kmer_len: usize,
+    pub canonical: bool,
+    /// The fraction of the k-mer space that was sampled
+    pub fraction: f64,
+    pub file2taxid: Vec<(String, usize)>,
+    /// The (sorted) indices of the files that contain each sampled k-mer
+    pub kmer_files: Vec<Vec<u32>>,
+}
+
+impl MuskFile for KmerSample {
+    const KINDS: &'static [FileKind] = &[FileKind::KmerSample];
+}
+
+/// The estimated size of a database built from an ordering of the files
+pub struct SizeEstimate {
+    pub kmers: f64,
+    pub blocks: f64,
+    /// Each run length encoding (its length and blocks) and kmer index entry of a database (.db)
+    pub database_bytes: f64,
+    /// Each kmer, block offset and blocks of a mapped database (.mdb)
+    pub mapped_bytes: f64,
+}
+
+impl KmerSample {
+    /// Samples the k-mers whose hash is in the lowest `fraction` of hashes, so the same k-mers
+    /// are sampled from every file
+    pub fn from_bitmaps(
+        bitmaps: &[RoaringTreemap],
+        fraction: f64,
+        kmer_len: usize,
+        canonical: bool,
+        file2taxid: Vec<(String, usize)>,
+    ) -> Self {
+        let mut kmer_to_files: HashMap<u64, Vec<u32>> = HashMap::new();
+        for (index, bitmap) in bitmaps.iter().enumerate() {
+            for kmer in sample_bitmap(bitmap, fraction) {
+                kmer_to_files.entry(kmer).or_default().push(index as u32);
+            }
+        }
+
+        KmerSample {
+            kmer_len,
+            canonical,
+            fraction,
+            file2taxid,
+            kmer_files: kmer_to_files.into_values().collect(),
+        }
+    }
+
+    /// Estimates the size of a database of the files in `ordering` (indices into the file2taxid).
+    /// Files that are not in the ordering are left out of the database.
+    pub fn estimate_size(&self, ordering: &[usize]) -> SizeEstimate {
+        let positions = file_positions(ordering, self.file2taxid.len());
+        let (kmers, blocks) = self
+            .kmer_files
+            .par_iter()
+            .map(|files| {
+                let blocks = count_blocks(files, |file| positions[file]);
+                ((blocks > 0) as usize, blocks)
+            })
+            .reduce(
+                || (0, 0),
+                |(kmers_1, blocks_1), (kmers_2, blocks_2)| (kmers_1 + kmers_2, blocks_1 + blocks_2),
+            );
+
+        let (kmers, blocks) = (kmers as f64 / self.fraction, blocks as f64 / self.fraction);
+        let kmer_bytes = if self.kmer_len > MAX_U32_KMER_LEN {
+            8.0
+        } else {
+            4.0
+        };
+        SizeEstimate {
+            kmers,
+            blocks,
+            database_bytes: kmers * (8.0 + kmer_bytes + 4.0) + blocks * 2.0,
+            mapped_bytes: kmers * (kmer_bytes + 8.0) + blocks * 2.0,
+        }
+    }
+
+    /// Improves an ordering (of every file or a subset of them) by moving single files next to
+    /// one of their `num_neighbors` closest files in the ordering (by `distances`) when it lowers
+    /// the number of blocks.
+    /// Moves are ranked by the blocks saved in the k-mers of the moved file and the files it leaves
+    /// or joins. A move is only made if the blocks saved, summed over the k-mers of every file
+    /// between its old and new place (which all shift), are positive. Some k-mers may gain blocks,
+    /// but the estimated blocks go down with each move, so the search always ends.
+    /// Returns how many fewer blocks are estimated.
+    pub fn block_local_search<D: FileDistances>(
+        &self,
+        ordering: &mut Vec<usize>,
+        distances: &D,
+        num_neighbors: usize,
+    ) -> f64 {
+        let original_blocks = self.estimate_size(ordering).blocks;
+
+        let num_files = self.file2taxid.len();
+        let mut file_kmers = vec![vec![]; num_files];
+        for (kmer, files) in self.kmer_files.iter().enumerate() {
+            for file in files {
+                file_kmers[*file as usize].push(kmer);
+            }
+        }
+        let mut positions = file_positions(ordering, num_files);
+        let neighbors = (0..num_files)
+            .into_par_iter()
+            .map(|file| match positions[file] {
+                Some(_) => closest_files(distances, file, num_neighbors, |neighbor| {
+                    positions[neighbor].is_some()
+                }),
+                None => vec![],
+            })
+            .collect::<Vec<Vec<usize>>>();
+
+        let mut improved = true;
+        while improved {
+            improved = false;
+            for file in ordering.clone() {
+                let from = positions[file].unwrap();
+                // Each move inserts the file at `to` in the ordering without it
+                let mut moves = neighbors[file]
+                    .par_iter()
+                    .flat_map_iter(|neighbor| {
+                        let neighbor_position = positions[*neighbor].unwrap();
+                        let removed_position =
+                            neighbor_position - (neighbor_position > from) as usize;
+                        [removed_position, removed_position + 1]
+                    })
+                    .filter(|to| *to != from)
+                    .map(|to| {
+                        let changed_positions = neighboring_positions(ordering.len(), from, to);
+                        let saved = self.blocks_saved(
+                            ordering,
+                            &positions,
+                            &file_kmers,
+                            from,
+                            to,
+                            &changed_positions,
+                        );
+                        (saved, to)
+                    })
+                    .filter(|(saved, _to)| *saved > 0)
+                    .collect::<Vec<(i64, usize)>>();
+                moves.sort_unstable_by_key(|(saved, to)| (std::cmp::Reverse(*saved), *to));
+                moves.dedup();
+
+                // Every file between `from` and `to` (inclusive) changes position
+                let best_move = moves.into_iter().find(|(_saved, to)| {
+                    let moved_positions = (from.min(*to)..=from.max(*to)).collect::<Vec<usize>>();
+                    self.blocks_saved(
+                        ordering,
+                        &positions,
+                        &file_kmers,
+                        from,
+                        *to,
+                        &moved_positions,
+                    ) > 0
+                });
+
+                if let Some((_saved, to)) = best_move {
+                    ordering.remove(from);
+                    ordering.insert(to, file);
+                    positions = file_positions(ordering, num_files);
+                    improved = true;
+                }
+            }
+        }
+
+        original_blocks - self.estimate_size(ordering).blocks
+    }
+
+    // The number of blocks saved in the k-mers of the files at `changed_positions` by moving the
+    // file at `from` to `to` (in the ordering without it)
+    fn blocks_saved(
+        &self,
+        ordering: &[usize],
+        positions: &[Option<usize>],
+        file_kmers: &[Vec<usize>],
+        from: usize,
+        to: usize,
+        changed_positions: &[usize],
+    ) -> i64 {
+        let moved_position = |position: usize| {
+            if position == from {
+                to
+            } else {
+                let removed_position = position - (position > from) as usize;
+                removed_position + (removed_position >= to) as usize
+            }
+        };
+
+        let mut kmers = changed_positions
+            .iter()
+            .flat_map(|position| file_kmers[ordering[*position]].iter().copied())
+            .collect::<Vec<usize>>();
+        kmers.sort_unstable();
+        kmers.dedup();
+
+        kmers
+            .into_iter()
+            .map(|kmer| {
+                let files = &self.kmer_files[kmer];
+                let before = count_blocks(files, |file| positions[file]);
+                let after = count_blocks(files, |file| positions[file].map(moved_position));
+                before as i64 - after as i64
+            })
+            .sum()
+    }
+}
+
+/// The k-mers of a bitmap that a k-mer sample of `fraction` keeps.
+/// Sampling each bitmap up front gives the same k-mer sample while keeping less in memory.
+pub fn sample_bitmap(bitmap: &RoaringTreemap, fraction: f64) -> RoaringTreemap {
+    let max_hash = (fraction * u64::MAX as f64) as u64;
+    bitmap
+        .iter()
+        .filter(|kmer| hash(*kmer) <= max_hash)
+        .collect()
+}
+
+// The position of each file in the ordering, `None` if it is not in the ordering
+fn file_positions(ordering: &[usize], num_files: usize) -> Vec<Option<usize>> {
+    let mut positions = vec![None; num_files];
+    for (position, file) in ordering.iter().enumerate() {
+        positions[*file] = Some(position);
+    }
+    positions
+}
+
+// The positions of the files whose neighbors change when the file at `from` is moved to `to`
+fn neighboring_positions(num_positions: usize, from: usize, to: usize) -> Vec<usize> {
+    let without_from = |position: usize| position + (position >= from) as usize;
+    let mut changed_positions = vec![from];
+    changed_positions.extend(from.checked_sub(1));
+    changed_positions.push(from + 1);
+    changed_positions.extend(to.checked_sub(1).map(without_from));
+    changed_positions.push(without_from(to));
+    changed_positions.retain(|position| *position < num_positions);
+    changed_positions
+}
+
+// The number of blocks in the run length encoding of a k-mer that is in `files`
+fn count_blocks<F: Fn(usize) -> Option<usize>>(files: &[u32], position: F) -> usize {
+    let mut file_positions = files
+        .iter()
+        .filter_map(|file| position(*file as usize))
+        .collect::<Vec<usize>>();
+    file_positions.sort_unstable();
+
+    let mut naive_rle = NaiveRunLengthEncoding::new();
+    for file_position in file_positions {
+        naive_rle.push(file_position);
+    }
+    naive_rle.to_rle().num_of_blocks()
+}
+
+// The `num_neighbors` closest files to a file that are `included`
+fn closest_files<D: FileDistances, F: Fn(usize) -> bool>(
+    distances: &D,
+    file: usize,
+    num_neighbors: usize,
+    included: F,
+) -> Vec<usize> {
+    let mut neighbors = distances
+        .neighbors(file)
+        .filter(|(neighbor, _distance)| included(*neighbor))
+        .map(|(neighbor, distance)| (distance, neighbor))
+        .collect::<Vec<(u32, usize)>>();
+    neighbors.sort_unstable();
+    neighbors
+        .into_iter()
+        .take(num_neighbors)
+        .map(|(_distance, neighbor)| neighbor)
+        .collect()
+}
@@ -0,0 +1,159 @@
+use roaring::RoaringTreemap;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::sampling::hash;
+
+/// A bottom-k MinHash sketch of the k-mers of a file (or group of files).
+/// Holds the `size` smallest k-mer hashes and the number of distinct k-mers, which is enough to
+/// estimate the hamming distance to another sketch without keeping the k-mers in memory.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Sketch {
+    size: usize,
+    num_kmers: u64,
+    /// Sorted, at most `size` hashes
+    hashes: Vec<u64>,
+}
+
+impl Sketch {
+    pub fn from_bitmap(bitmap: &RoaringTreemap, size: usize) -> Self {
+        // A max heap of the smallest hashes seen so far
+        let mut heap = BinaryHeap::with_capacity(size + 1);
+        for kmer in bitmap.iter() {
+            let kmer_hash = hash(kmer);
+            if heap.len() < size {
+                heap.push(kmer_hash);
+            } else if heap.peek().is_some_and(|max_hash| kmer_hash < *max_hash) {
+                heap.pop();
+                heap.push(kmer_hash);
+            }
+        }
+
+        Sketch {
+            size,
+            num_kmers: bitmap.len(),
+            hashes: heap.into_sorted_vec(),
+        }
+    }
+
+    /// The number of distinct k-mers in the sketched file
+    pub fn num_kmers(&self) -> u64 {
+        self.num_kmers
+    }
+
+    /// Estimates the jaccard index |A & B| / |A | B| from the smallest hashes of A | B
+    pub fn jaccard(&self, other: &Sketch) -> f64 {
+        // The smallest hashes of the union are in both sketches, up to the smaller sketch size
+        // (a sketch with fewer hashes than its size holds every hash of its file)
+        let (mut index_1, mut index_2) = (0, 0);
+        let (mut union_size, mut shared) = (0, 0);
+        while union_size < self.size.min(other.size) {
+            let next = match (self.hashes.get(index_1), other.hashes.get(index_2)) {
+                (Some(hash_1), Some(hash_2)) => hash_1.cmp(hash_2),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => break,
+            };
+            match next {
+                Ordering::Less => index_1 += 1,
+                Ordering::Greater => index_2 += 1,
+                Ordering::Equal => {
+                    shared += 1;
+                    index_1 += 1;
+                    index_2 += 1;
+                }
+            }
+            union_size += 1;
+        }
+
+        if union_size == 0 {
+            return 1.0;
+        }
+        shared as f64 / union_size as f64
+    }
+
+    /// Estimates the hamming distance |A| + |B| - (2 * |A & B|)
+    pub fn hamming_distance(&self, other: &Sketch) -> u32 {
+        let jaccard = self.jaccard(other);
+        let total = (self.num_kmers + other.num_kmers) as f64;
+        // |A & B| = J * |A | B| = J * (|A| + |B| - |A & B|)
+        let intersection_size =
+            (jaccard * total / (1.0 + jaccard)).min(self.num_kmers.min(other.num_kmers) as f64);
+        (total - 2.0 * intersection_size).round() as u32
+    }
+}
+
+/// The sketches that hold each hash.
+/// Two sketches can only have an estimated jaccard index above zero if they share a hash, so the
+/// nearest sketches of each sketch are found without comparing every pair of sketches.
+pub struct SketchIndex {
+    hash_sketches: HashMap<u64, Vec<u32>>,
+    /// Every sketch from fewest to most k-mers
+    by_size: Vec<u32>,
+}
+
+impl SketchIndex {
+    pub fn new(sketches: &[Sketch]) -> Self {
+        let mut hash_sketches: HashMap<u64, Vec<u32>> = HashMap::new();
+        for (index, sketch) in sketches.iter().enumerate() {
+            for hash in &sketch.hashes {
+                hash_sketches.entry(*hash).or_default().push(index as u32);
+            }
+        }
+        let mut by_size = (0..sketches.len() as u32).collect::<Vec<u32>>();
+        by_size.sort_by_key(|index| sketches[*index as usize].num_kmers);
+
+        SketchIndex {
+            hash_sketches,
+            by_size,
+        }
+    }
+
+    /// The (sorted) sketches other than `index` that share at least one hash with it
+    pub fn sharing(&self, sketches: &[Sketch], index: usize) -> Vec<u32> {
+        let mut sharing = sketches[index]
+            .hashes
+            .iter()
+            .flat_map(|hash| self.hash_sketches[hash].iter().copied())
+            .filter(|other| *other as usize != index)
+            .collect::<Vec<u32>>();
+        sharing.sort_unstable();
+        sharing.dedup();
+        sharing
+    }
+
+    /// The `num_neighbors` sketches with the smallest estimated hamming distance to the sketch
+    /// at `index`, as (distance, sketch) from nearest to furthest.
+    /// The same as comparing every sketch, ties are broken by the lower sketch index.
+    pub fn nearest(
+        &self,
+        sketches: &[Sketch],
+        index: usize,
+        num_neighbors: usize,
+    ) -> Vec<(u32, u32)> {
+        let sketch = &sketches[index];
+        let sharing = self.sharing(sketches, index);
+
+        // Every other sketch is at |A| + |B|, so only the smallest of them can be nearer
+        let not_sharing = self
+            .by_size
+            .iter()
+            .copied()
+            .filter(|other| *other as usize != index && sharing.binary_search(other).is_err())
+            .take(num_neighbors);
+
+        let mut nearest = sharing
+            .iter()
+            .copied()
+            .chain(not_sharing)
+            .map(|other| (sketch.hamming_distance(&sketches[other as usize]), other))
+            .collect::<Vec<(u32, u32)>>();
+        if nearest.len() > num_neighbors {
+            nearest.select_nth_unstable(num_neighbors);
+            nearest.truncate(num_neighbors);
+        }
+        nearest.sort_unstable();
+        nearest
+    }
+}
@@ -1,22 +1,60 @@
-use bio::io::{fasta, fastq};
+use bio::io::{fasta, fastq, fastx};
+use flate2::bufread::MultiGzDecoder;
 use rayon::prelude::*;
-use roaring::RoaringBitmap;
+use roaring::RoaringTreemap;
 use std::fs::File;
 use std::fs::{self, DirEntry};
-use std::io::BufReader;
+use std::io::{self, BufRead, BufReader, Read};
 use std::path::Path;
 use std::path::PathBuf;
 use tracing::{error, warn};
 
-use crate::kmer_iter::KmerIter;
+use crate::kmer_iter::SpacedSeed;
+use crate::sampling::Sampling;
 
 pub const XOR_NUMBER: usize = 188_888_881;
 
+// Magic bytes at the start of compressed files (bgzip files are also gzip files)
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+const FASTA_EXTENSIONS: [&str; 3] = [".fna", ".fasta", ".fa"];
+const COMPRESSED_EXTENSIONS: [&str; 4] = ["", ".gz", ".bgz", ".zst"];
+
+/// A reader over the (decompressed) contents of an input file
+pub type InputReader = Box<dyn Read + Send>;
+
 fn is_fasta_file(entry: &DirEntry) -> bool {
     let entry_file_name = entry.file_name().to_str().unwrap().to_string();
-    entry_file_name.ends_with(".fna")
-        || entry_file_name.ends_with(".fasta")
-        || entry_file_name.ends_with(".fa")
+    FASTA_EXTENSIONS.iter().any(|fasta_extension| {
+        COMPRESSED_EXTENSIONS.iter().any(|compressed_extension| {
+            entry_file_name.ends_with(&format!("{}{}", fasta_extension, compressed_extension))
+        })
+    })
+}
+
+/// Opens a file for reading, transparently decompressing it if it is gzip/bgzip or zstd compressed.
+/// The compression is detected using the magic bytes at the start of the file, not the extension.
+/// If the path is '-', stdin is read instead.
+pub fn open_input_file(file_path: &Path) -> io::Result<InputReader> {
+    if file_path == Path::new("-") {
+        decompress(io::stdin())
+    } else {
+        decompress(File::open(file_path)?)
+    }
+}
+
+fn decompress<R: Read + Send + 'static>(reader: R) -> io::Result<InputReader> {
+    let mut buf_reader = BufReader::new(reader);
+    let start = buf_reader.fill_buf()?;
+
+    if start.starts_with(&GZIP_MAGIC) {
+        Ok(Box::new(MultiGzDecoder::new(buf_reader)))
+    } else if start.starts_with(&ZSTD_MAGIC) {
+        Ok(Box::new(zstd::Decoder::with_buffer(buf_reader)?))
+    } else {
+        Ok(Box::new(buf_reader))
+    }
 }
 
 pub fn get_fasta_files(reference_loc: &Path) -> Vec<PathBuf> {
@@ -30,7 +68,7 @@ pub fn get_fasta_files(reference_loc: &Path) -> Vec<PathBuf> {
                     Some(entry.path())
                 } else {
                     warn!(
-                        "directory entry {:?} did not end with '.fna', '.fasta', or '.fa', skipping...",
+                        "directory entry {:?} did not end with '.fna', '.fasta', or '.fa' (optionally followed by '.gz', '.bgz', or '.zst'), skipping...",
                         entry
                     );
                     None
@@ -49,31 +87,47 @@ pub fn get_fasta_files(reference_loc: &Path) -> Vec<PathBuf> {
         .collect::<Vec<PathBuf>>()
 }
 
-pub fn get_fasta_iter_of_file(file_path: &Path) -> fasta::Records<BufReader<File>> {
-    match fasta::Reader::from_file(file_path) {
-        Ok(reader) => reader.records(),
-        Err(error) => panic!("{}", error),
+pub fn get_fasta_iter_of_file(file_path: &Path) -> fasta::Records<BufReader<InputReader>> {
+    match open_input_file(file_path) {
+        Ok(reader) => fasta::Reader::new(reader).records(),
+        Err(error) => panic!("could not open fasta file at {:?}: {}", file_path, error),
+    }
+}
+
+pub fn get_fastq_iter_of_file(file_path: &Path) -> fastq::Records<BufReader<InputReader>> {
+    match open_input_file(file_path) {
+        Ok(reader) => fastq::Reader::new(reader).records(),
+        Err(error) => panic!("could not open fastq file at {:?}: {}", file_path, error),
     }
 }
 
-pub fn get_fastq_iter_of_file(file_path: &Path) -> fastq::Records<BufReader<File>> {
-    match fastq::Reader::from_file(file_path) {
-        Ok(reader) => reader.records(),
-        Err(error) => panic!("{}", error),
+/// Returns an iterator over the records of a reads file, which may be either FASTA or FASTQ.
+/// The format is detected from the first character of the (decompressed) file.
+pub fn get_reads_iter_of_file(file_path: &Path) -> fastx::EitherRecords<BufReader<InputReader>> {
+    match open_input_file(file_path) {
+        Ok(reader) => fastx::EitherRecords::new(BufReader::new(reader)),
+        Err(error) => panic!("could not open reads file at {:?}: {}", file_path, error),
     }
 }
 
-// Creates a single bitmap containing k-mers from all files, if necessary
-pub fn create_bitmap(files: Vec<PathBuf>, kmer_len: usize, canonical: bool) -> RoaringBitmap {
-    let mut bitmap = RoaringBitmap::new();
+// Creates a single bitmap containing (sampled) k-mers from all files, if necessary
+pub fn create_bitmap(
+    files: Vec<PathBuf>,
+    kmer_len: usize,
+    spaced_seed: Option<&SpacedSeed>,
+    canonical: bool,
+    sampling: Sampling,
+) -> RoaringTreemap {
+    let mut bitmap = RoaringTreemap::new();
+    let span = spaced_seed.map_or(kmer_len, |spaced_seed| spaced_seed.span());
     for file in files {
         let mut record_iter = get_fasta_iter_of_file(&file);
         while let Some(Ok(record)) = record_iter.next() {
-            if record.seq().len() < kmer_len {
+            if record.seq().len() < span {
                 continue;
             }
-            for kmer in KmerIter::from(record.seq(), kmer_len, canonical) {
-                bitmap.insert(kmer as u32);
+            for kmer in sampling.kmers(record.seq(), kmer_len, spaced_seed, canonical) {
+                bitmap.insert(kmer);
             }
         }
     }
@@ -0,0 +1,87 @@
+use musk::checkpoint::{describe_file2taxid, WorkDirectory};
+use roaring::RoaringTreemap;
+use std::fs;
+use std::path::PathBuf;
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "musk_checkpoint_test_{}_{}",
+        std::process::id(),
+        name
+    ))
+}
+
+#[test]
+fn saved_results_are_resumed() {
+    let path = temp_path("resume");
+    let bitmap = RoaringTreemap::from_iter([1_u64, 5, 9, 1 << 40]);
+
+    let work_dir = WorkDirectory::open(&path, "k-mer length 14");
+    assert!(!work_dir.contains("bitmap_0"));
+    let created = work_dir.load_or_create("bitmap_0", || bitmap.clone());
+    assert_eq!(created, bitmap);
+    assert!(work_dir.contains("bitmap_0"));
+
+    // Opening the directory again loads the saved bitmap instead of creating it
+    let work_dir = WorkDirectory::open(&path, "k-mer length 14");
+    let resumed: RoaringTreemap =
+        work_dir.load_or_create("bitmap_0", || panic!("bitmap was created again"));
+    assert_eq!(resumed, bitmap);
+
+    // A result that can not be loaded is created again
+    fs::write(path.join("musk_work").join("row_0"), [1_u8]).unwrap();
+    let row = work_dir.load_or_create("row_0", || vec![0_u32, 3, 7]);
+    assert_eq!(row, vec![0, 3, 7]);
+
+    // Only the work directory inside the given directory is removed
+    fs::write(path.join("output.musk.db"), [1_u8]).unwrap();
+    work_dir.remove();
+    assert!(!path.join("musk_work").exists());
+    assert!(path.join("output.musk.db").exists());
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn other_files_are_kept() {
+    let path = temp_path("other_files");
+    let work_dir = WorkDirectory::open(&path, "k-mer length 14");
+    work_dir.load_or_create("bitmap_0", || vec![1_u32]);
+    fs::write(path.join("musk_work").join("notes.txt"), [1_u8]).unwrap();
+
+    // The directory is kept with the files that were not saved by the work directory
+    work_dir.remove();
+    let remaining = fs::read_dir(path.join("musk_work"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect::<Vec<_>>();
+    assert_eq!(remaining, vec!["notes.txt"]);
+
+    // A directory with other files is not used as a work directory
+    let result = std::panic::catch_unwind(|| WorkDirectory::open(&path, "k-mer length 14"));
+    fs::remove_dir_all(&path).unwrap();
+    assert!(result.is_err());
+}
+
+#[test]
+#[should_panic(expected = "different settings")]
+fn different_settings_are_refused() {
+    let path = temp_path("settings");
+    WorkDirectory::open(&path, "k-mer length 14");
+    let result = std::panic::catch_unwind(|| WorkDirectory::open(&path, "k-mer length 15"));
+    fs::remove_dir_all(&path).unwrap();
+    if let Err(e) = result {
+        std::panic::resume_unwind(e);
+    }
+}
+
+#[test]
+fn file2taxid_description() {
+    let file2taxid = vec![("a.fna".to_string(), 1), ("b.fna".to_string(), 2)];
+    let mut reordered = file2taxid.clone();
+    reordered.reverse();
+    assert_ne!(
+        describe_file2taxid(&file2taxid),
+        describe_file2taxid(&reordered)
+    );
+    assert!(describe_file2taxid(&file2taxid).starts_with("2 groups"));
+}
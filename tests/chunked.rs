@@ -0,0 +1,127 @@
+use musk::chunked::ChunkedBuilder;
+use musk::database::Database;
+use musk::kmer_index::IndexKind;
+use musk::sampling::Sampling;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use roaring::RoaringTreemap;
+use std::fs::{self, File};
+use std::path::PathBuf;
+
+const KMER_LEN: usize = 8;
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("musk_chunked_test_{}_{}", std::process::id(), name))
+}
+
+// Files share most of their kmers with the previous file, like an ordered file2taxid
+fn random_bitmaps() -> Vec<RoaringTreemap> {
+    let mut rng = StdRng::seed_from_u64(7);
+    let mut kmers = (0..2_000)
+        .map(|_| rng.random_range(0..4_u64.pow(KMER_LEN as u32)))
+        .collect::<Vec<u64>>();
+    (0..40)
+        .map(|_| {
+            for kmer in kmers.iter_mut() {
+                if rng.random_bool(0.05) {
+                    *kmer = rng.random_range(0..4_u64.pow(KMER_LEN as u32));
+                }
+            }
+            kmers.iter().copied().collect()
+        })
+        .collect()
+}
+
+// Builds a chunked database and checks it is the same as the mapped database built in memory.
+// Returns the estimated memory of each chunk.
+fn assert_chunked_build_matches(
+    bitmaps: Vec<RoaringTreemap>,
+    memory_limit: u64,
+    name: &str,
+) -> Vec<u64> {
+    let files = (0..bitmaps.len())
+        .map(|i| format!("{}.fna", i))
+        .collect::<Vec<String>>();
+    let tax_ids = (0..bitmaps.len()).collect::<Vec<usize>>();
+
+    let work_dir = temp_path(&format!("{}_work", name));
+    let builder = ChunkedBuilder::new(
+        bitmaps.len(),
+        KMER_LEN,
+        None,
+        true,
+        Sampling::All,
+        &work_dir,
+    )
+    .unwrap();
+    for (index, bitmap) in bitmaps.iter().enumerate().rev() {
+        builder.add_bitmap(index, bitmap).unwrap();
+    }
+    let chunked_path = temp_path(&format!("{}_chunked.mdb", name));
+    let chunk_memories = builder
+        .finish(
+            files.clone(),
+            tax_ids.clone(),
+            memory_limit,
+            File::create(&chunked_path).unwrap(),
+        )
+        .unwrap();
+
+    let database = Database::from(
+        bitmaps,
+        true,
+        files,
+        tax_ids,
+        KMER_LEN,
+        None,
+        Sampling::All,
+        IndexKind::HashMap,
+    );
+    let mapped_path = temp_path(&format!("{}_mapped.mdb", name));
+    database
+        .dump_mapped(File::create(&mapped_path).unwrap())
+        .unwrap();
+
+    assert_eq!(
+        fs::read(&chunked_path).unwrap(),
+        fs::read(&mapped_path).unwrap()
+    );
+    // Only the (empty) work directory is left behind
+    assert_eq!(fs::read_dir(&work_dir).unwrap().count(), 0);
+
+    fs::remove_dir(&work_dir).unwrap();
+    fs::remove_file(&chunked_path).unwrap();
+    fs::remove_file(&mapped_path).unwrap();
+    chunk_memories
+}
+
+#[test]
+fn chunked_build_matches_mapped_database() {
+    // A tiny memory limit so that every kmer is its own chunk
+    let chunk_memories = assert_chunked_build_matches(random_bitmaps(), 1, "tiny");
+    let num_kmers = random_bitmaps()
+        .into_iter()
+        .reduce(|union, bitmap| union | bitmap)
+        .unwrap()
+        .len();
+    assert_eq!(chunk_memories.len() as u64, num_kmers);
+}
+
+#[test]
+fn chunks_follow_memory_limit() {
+    // Every kmer starts with the same four nucleotides, so they all fall in the first bucket
+    let mut rng = StdRng::seed_from_u64(11);
+    let bitmaps = (0..40)
+        .map(|_| {
+            (0..200)
+                .map(|_| rng.random_range(0..4_u64.pow(KMER_LEN as u32 - 4)))
+                .collect::<RoaringTreemap>()
+        })
+        .collect::<Vec<RoaringTreemap>>();
+
+    let memory_limit = 20_000;
+    let chunk_memories = assert_chunked_build_matches(bitmaps, memory_limit, "limit");
+    assert!(chunk_memories.len() > 1);
+    assert!(chunk_memories
+        .iter()
+        .all(|chunk_memory| *chunk_memory <= memory_limit));
+}
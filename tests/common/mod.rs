@@ -0,0 +1,24 @@
+use taxonomy::{GeneralTaxonomy, TaxRank};
+
+// 1 (root) -> 2 (superkingdom) -> 10 (genus) -> 11, 12 (species)
+//                              -> 20 (no rank) -> 21 (species)
+pub fn small_taxonomy() -> GeneralTaxonomy {
+    let nodes = [
+        (1, 0, "root", TaxRank::Unspecified),
+        (2, 0, "Bacteria", TaxRank::Superkingdom),
+        (10, 1, "Genus A", TaxRank::Genus),
+        (11, 2, "Species A1", TaxRank::Species),
+        (12, 2, "Species A2", TaxRank::Species),
+        (20, 1, "Group B", TaxRank::Unspecified),
+        (21, 5, "Species B1", TaxRank::Species),
+    ];
+    GeneralTaxonomy::from_arrays(
+        nodes.iter().map(|node| node.0.to_string()).collect(),
+        nodes.iter().map(|node| node.1).collect(),
+        Some(nodes.iter().map(|node| node.2.to_string()).collect()),
+        Some(nodes.iter().map(|node| node.3).collect()),
+        None,
+        None,
+    )
+    .unwrap()
+}
@@ -0,0 +1,405 @@
+use itertools::Itertools;
+use musk::big_exp_float::BigExpFloat;
+use musk::consts::BinomialConsts;
+use musk::database::Database;
+use musk::kmer_index::{IndexKind, KmerIndex};
+use musk::kmer_iter::SpacedSeed;
+use musk::rle::{collect_indices, RunLengthEncoding};
+use musk::sampling::Sampling;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use roaring::RoaringTreemap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+
+const KMER_LEN: usize = 21;
+
+fn random_sequence(rng: &mut StdRng, length: usize) -> Vec<u8> {
+    (0..length)
+        .map(|_| b"ACGT"[rng.random_range(0..4)])
+        .collect()
+}
+
+// Each file is a random sequence, so reads from a file should only be assigned to that file
+fn random_sequence_database(
+    sequences: &[Vec<u8>],
+    spaced_seed: Option<SpacedSeed>,
+    sampling: Sampling,
+) -> Database {
+    let kmer_len = spaced_seed
+        .as_ref()
+        .map_or(KMER_LEN, |spaced_seed| spaced_seed.weight());
+    let bitmaps = sequences
+        .iter()
+        .map(|sequence| {
+            sampling
+                .kmers(sequence, kmer_len, spaced_seed.as_ref(), true)
+                .collect::<RoaringTreemap>()
+        })
+        .collect();
+    let files = (0..sequences.len()).map(|i| format!("{}.fna", i)).collect();
+    let tax_ids = (0..sequences.len()).collect();
+    Database::from(
+        bitmaps,
+        true,
+        files,
+        tax_ids,
+        kmer_len,
+        spaced_seed,
+        sampling,
+        IndexKind::HashMap,
+    )
+}
+
+fn assert_reads_assigned(database: &Database, sequences: &[Vec<u8>]) {
+    let n_max = 150 - KMER_LEN as u64 + 1;
+    let lookup_table = database.compute_loookup_table(n_max);
+    for (index, sequence) in sequences.iter().enumerate() {
+        // Add a few mismatches so that not every kmer is a hit
+        let mut read = sequence[1_000..1_150].to_vec();
+        for position in [30, 75, 120] {
+            read[position] = if read[position] == b'A' { b'C' } else { b'A' };
+        }
+        let (classification, _) = database.classify(
+            &read,
+            BigExpFloat::from_f64(1e-10),
+            n_max,
+            &lookup_table,
+            None,
+        );
+        assert_eq!(
+            classification.assignment(),
+            Some((format!("{}.fna", index).as_str(), index))
+        );
+    }
+}
+
+fn random_sequences() -> Vec<Vec<u8>> {
+    let mut rng = StdRng::seed_from_u64(42);
+    (0..4)
+        .map(|_| random_sequence(&mut rng, 5_000))
+        .collect::<Vec<Vec<u8>>>()
+}
+
+#[test]
+fn large_kmers_classify() {
+    let sequences = random_sequences();
+    let database = random_sequence_database(&sequences, None, Sampling::All);
+
+    let path = std::env::temp_dir().join(format!("musk_database_test_{}.mdb", std::process::id()));
+    database.dump_mapped(File::create(&path).unwrap()).unwrap();
+    let mapped = Database::load(&path);
+
+    assert_reads_assigned(&database, &sequences);
+    assert_reads_assigned(&mapped, &sequences);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn sampled_kmers_classify() {
+    let sequences = random_sequences();
+    for sampling in [
+        Sampling::Minimizer { window: 10 },
+        Sampling::OpenSyncmer { smer_len: 15 },
+    ] {
+        let database = random_sequence_database(&sequences, None, sampling);
+        let all_kmers = random_sequence_database(&sequences, None, Sampling::All);
+        assert!(database.num_kmers() < all_kmers.num_kmers() / 3);
+        assert_reads_assigned(&database, &sequences);
+    }
+}
+
+#[test]
+fn spaced_seed_classify() {
+    let sequences = random_sequences();
+    let spaced_seed = "110110110110110110110111".parse::<SpacedSeed>().unwrap();
+    let database = random_sequence_database(&sequences, Some(spaced_seed.clone()), Sampling::All);
+    assert_eq!(database.kmer_len(), spaced_seed.weight());
+
+    let path = std::env::temp_dir().join(format!(
+        "musk_database_test_spaced_{}.mdb",
+        std::process::id()
+    ));
+    database.dump_mapped(File::create(&path).unwrap()).unwrap();
+    let mapped = Database::load(&path);
+    assert_eq!(mapped.spaced_seed(), Some(&spaced_seed));
+
+    assert_reads_assigned(&database, &sequences);
+    assert_reads_assigned(&mapped, &sequences);
+
+    fs::remove_file(&path).unwrap();
+}
+
+fn reverse_complement(sequence: &[u8]) -> Vec<u8> {
+    sequence
+        .iter()
+        .rev()
+        .map(|base| match base {
+            b'A' => b'T',
+            b'C' => b'G',
+            b'G' => b'C',
+            b'T' => b'A',
+            other => *other,
+        })
+        .collect()
+}
+
+#[test]
+fn pair_hits_are_combined() {
+    let sequences = random_sequences();
+    let database = random_sequence_database(&sequences, None, Sampling::All);
+    let n_max = 2 * (150 - KMER_LEN as u64 + 1);
+    let lookup_table = database.compute_loookup_table(n_max);
+    let cutoff_threshold = BigExpFloat::from_f64(1e-10);
+
+    // The second mate is read from the opposite strand, further along the fragment
+    let mut read_1 = sequences[2][1_000..1_150].to_vec();
+    read_1[75] = if read_1[75] == b'A' { b'C' } else { b'A' };
+    let read_2 = reverse_complement(&sequences[2][1_300..1_450]);
+
+    let classify = |read: &[u8]| {
+        database
+            .classify(read, cutoff_threshold, n_max, &lookup_table, None)
+            .0
+    };
+    let (mate_1, mate_2) = (classify(&read_1), classify(&read_2));
+    let (pair, _) = database.classify_pair(
+        &read_1,
+        &read_2,
+        cutoff_threshold,
+        n_max,
+        &lookup_table,
+        None,
+    );
+
+    assert_eq!(pair.assignment(), Some(("2.fna", 2)));
+    assert_eq!(pair.num_queries, mate_1.num_queries + mate_2.num_queries);
+    // The hits of both mates are counted together, which makes the pair more significant
+    let best = &pair.candidates[0];
+    assert_eq!(
+        best.num_hits,
+        mate_1.candidates[0].num_hits + mate_2.candidates[0].num_hits
+    );
+    assert!(best.probability < mate_1.candidates[0].probability);
+    assert!(best.probability < mate_2.candidates[0].probability);
+
+    // A mate without hits only adds queries
+    let (unmatched, _) = database.classify_pair(
+        &read_1,
+        &[b'N'; 150],
+        cutoff_threshold,
+        n_max,
+        &lookup_table,
+        None,
+    );
+    assert_eq!(unmatched.assignment(), Some(("2.fna", 2)));
+    assert_eq!(
+        unmatched.candidates[0].num_hits,
+        mate_1.candidates[0].num_hits
+    );
+}
+
+#[test]
+fn kmer_space_size() {
+    let bitmaps = || vec![RoaringTreemap::from_iter(0_u64..24)];
+    let database = |canonical| {
+        Database::from(
+            bitmaps(),
+            canonical,
+            vec!["a.fna".to_string()],
+            vec![1],
+            4,
+            None,
+            Sampling::All,
+            IndexKind::HashMap,
+        )
+    };
+    // There are 4^4 = 256 k-mers, but only (256 - 16) / 2 = 120 canonical k-mers
+    assert_eq!(database(false).p_values(), &[24.0 / 256.0]);
+    assert_eq!(database(true).p_values(), &[24.0 / 120.0]);
+}
+
+fn bitmap(kmers: &[u64]) -> RoaringTreemap {
+    RoaringTreemap::from_iter(kmers.iter().copied())
+}
+
+// A database of 4-mers where the tax id of each file is its position in the alphabet
+fn small_database(bitmaps: Vec<RoaringTreemap>, files: &[&str]) -> Database {
+    Database::from(
+        bitmaps,
+        true,
+        files.iter().map(|file| file.to_string()).collect(),
+        files
+            .iter()
+            .map(|file| (file.as_bytes()[0] - b'a') as usize)
+            .collect(),
+        4,
+        None,
+        Sampling::All,
+        IndexKind::Dense,
+    )
+}
+
+fn assert_same_database(database: &Database, expected: &Database) {
+    assert_eq!(database.files(), expected.files());
+    assert_eq!(database.tax_ids(), expected.tax_ids());
+    assert_eq!(database.p_values(), expected.p_values());
+    assert_eq!(database.num_kmers(), expected.num_kmers());
+
+    let sorted_blocks = |database: &Database| {
+        (0..database.num_kmers())
+            .map(|position| database.rle_blocks(position).to_vec())
+            .sorted()
+            .collect_vec()
+    };
+    assert_eq!(sorted_blocks(database), sorted_blocks(expected));
+}
+
+#[test]
+fn update_matches_rebuild() {
+    let (a, b, c, d) = (
+        bitmap(&[1, 5, 9, 200]),
+        bitmap(&[5, 9, 17, 100]),
+        bitmap(&[0, 9, 255]),
+        bitmap(&[3, 5, 255]),
+    );
+
+    // Remove b and add d
+    let mut updated = small_database(vec![a.clone(), b, c.clone()], &["a", "b", "c"]);
+    updated.update(&[1], vec![d.clone()], vec!["d".to_string()], vec![3]);
+    let rebuilt = small_database(vec![a, c, d], &["a", "c", "d"]);
+
+    assert_same_database(&updated, &rebuilt);
+}
+
+#[test]
+fn subset_keeps_listed_files() {
+    let mut database = small_database(
+        vec![
+            bitmap(&[1, 5, 9, 200]),
+            bitmap(&[5, 9, 17, 100]),
+            bitmap(&[0, 9, 255]),
+            bitmap(&[3, 5, 255]),
+        ],
+        &["a", "b", "c", "d"],
+    );
+
+    // Keep a and c, the k-mers only in b or d (3, 17, and 100) are removed
+    database.update(&[1, 3], vec![], vec![], vec![]);
+    assert_eq!(database.files(), &["a", "c"]);
+    assert_eq!(database.tax_ids(), &[0, 2]);
+    assert_eq!(database.num_kmers(), 6);
+
+    // 0 and 255 are only in c, 1, 5, and 200 are only in a, and 9 is in both
+    let kmer_files = (0..database.num_kmers())
+        .map(|position| collect_indices(database.rle_blocks(position)))
+        .sorted()
+        .collect_vec();
+    assert_eq!(
+        kmer_files,
+        vec![vec![0], vec![0], vec![0], vec![0, 1], vec![1], vec![1]]
+    );
+}
+
+#[test]
+fn merge_matches_build() {
+    let (a, b, c) = (
+        bitmap(&[1, 5, 9, 200]),
+        bitmap(&[5, 9, 17, 100]),
+        bitmap(&[0, 9, 255]),
+    );
+
+    let mut merged = small_database(vec![a.clone(), b.clone()], &["a", "b"]);
+    merged.merge(&small_database(vec![c.clone()], &["c"]));
+    let built = small_database(vec![a, b, c], &["a", "b", "c"]);
+
+    assert_same_database(&merged, &built);
+}
+
+#[test]
+#[should_panic(expected = "different k-mer settings")]
+fn merge_refuses_different_settings() {
+    let mut database = small_database(vec![bitmap(&[1, 5])], &["a"]);
+    let other = Database::from(
+        vec![bitmap(&[1, 5])],
+        false,
+        vec!["b".to_string()],
+        vec![1],
+        4,
+        None,
+        Sampling::All,
+        IndexKind::Dense,
+    );
+    database.merge(&other);
+}
+
+// The layout of the current database file (after the header)
+#[derive(Deserialize)]
+struct SerializedDatabase {
+    canonical: bool,
+    consts: BinomialConsts,
+    files: Vec<String>,
+    rles: Vec<RunLengthEncoding>,
+    tax_ids: Vec<usize>,
+    kmer_len: usize,
+    spaced_seed: Option<SpacedSeed>,
+    sampling: Sampling,
+    kmer_index: KmerIndex,
+    p_values: Vec<f64>,
+}
+
+// The layout of a database file written before musk files had a header
+#[derive(Serialize)]
+struct HeaderlessDatabase {
+    canonical: bool,
+    consts: BinomialConsts,
+    files: Vec<String>,
+    rles: Vec<RunLengthEncoding>,
+    tax_ids: Vec<usize>,
+    kmer_len: usize,
+    kmer_to_rle_index: HashMap<u32, u32>,
+    p_values: Vec<f64>,
+}
+
+#[test]
+fn headerless_database_is_loaded() {
+    let database = Database::from(
+        vec![bitmap(&[1, 5, 9]), bitmap(&[5, 200])],
+        true,
+        vec!["a".to_string(), "b".to_string()],
+        vec![0, 1],
+        4,
+        None,
+        Sampling::All,
+        IndexKind::HashMap,
+    );
+    let serialized: SerializedDatabase =
+        bincode::deserialize(&bincode::serialize(&database).unwrap()).unwrap();
+    assert!(serialized.spaced_seed.is_none() && serialized.sampling == Sampling::All);
+    let KmerIndex::HashMap(kmer_to_rle_index) = serialized.kmer_index else {
+        panic!("the database does not have a hashmap index");
+    };
+    let headerless = HeaderlessDatabase {
+        canonical: serialized.canonical,
+        consts: serialized.consts,
+        files: serialized.files,
+        rles: serialized.rles,
+        tax_ids: serialized.tax_ids,
+        kmer_len: serialized.kmer_len,
+        kmer_to_rle_index,
+        p_values: serialized.p_values,
+    };
+
+    let path = std::env::temp_dir().join(format!(
+        "musk_database_test_{}_headerless.db",
+        std::process::id()
+    ));
+    bincode::serialize_into(File::create(&path).unwrap(), &headerless).unwrap();
+    let loaded = Database::load(&path);
+    fs::remove_file(&path).unwrap();
+
+    assert_same_database(&loaded, &database);
+    assert_eq!(loaded.kmer_len(), 4);
+    assert_eq!(loaded.sampling(), Sampling::All);
+}
@@ -0,0 +1,151 @@
+use musk::distances::{DistanceMeasure, PairwiseDistances, TextFormat};
+
+fn test_distances() -> PairwiseDistances {
+    PairwiseDistances {
+        kmer_len: Some(14),
+        canonical: true,
+        distances: vec![vec![0], vec![30, 0], vec![50, 70, 0]],
+        file2taxid: vec![
+            ("a.fna".to_string(), 1),
+            ("b.fna$c.fna".to_string(), 2),
+            ("d.fna".to_string(), 3),
+        ],
+        kmer_counts: Some(vec![100, 110, 120]),
+    }
+}
+
+fn exported(distances: &PairwiseDistances, format: TextFormat, measure: DistanceMeasure) -> String {
+    let mut text = vec![];
+    distances.write_text(&mut text, format, measure).unwrap();
+    String::from_utf8(text).unwrap()
+}
+
+#[test]
+fn text_round_trip() {
+    let distances = test_distances();
+    for format in [TextFormat::Phylip, TextFormat::Tsv] {
+        let text = exported(&distances, format, DistanceMeasure::Hamming);
+
+        // Importing with the file2taxid in another order reorders the distances
+        let mut file2taxid = distances.file2taxid.clone();
+        file2taxid.reverse();
+        let imported = PairwiseDistances::read_text(
+            text.as_bytes(),
+            format,
+            DistanceMeasure::Hamming,
+            file2taxid,
+            None,
+            Some(14),
+            true,
+        )
+        .unwrap();
+        for index_1 in 0..3 {
+            for index_2 in 0..3 {
+                assert_eq!(
+                    imported.measure(2 - index_1, 2 - index_2, DistanceMeasure::Hamming),
+                    distances.measure(index_1, index_2, DistanceMeasure::Hamming)
+                );
+            }
+        }
+        assert_eq!(imported.kmer_counts, None);
+    }
+}
+
+#[test]
+fn normalized_distances() {
+    let distances = test_distances();
+    // |A & B| = (100 + 110 - 30) / 2 = 90 and |A | B| = 120
+    let jaccard_distance = distances.measure(0, 1, DistanceMeasure::Jaccard);
+    assert!((jaccard_distance - 30.0 / 120.0).abs() < 1e-12);
+
+    let jaccard = 0.75_f64;
+    let mash_distance = distances.measure(1, 0, DistanceMeasure::Mash);
+    assert!((mash_distance + (2.0 * jaccard / (1.0 + jaccard)).ln() / 14.0).abs() < 1e-12);
+    assert_eq!(distances.measure(2, 2, DistanceMeasure::Mash), 0.0);
+
+    let text = exported(&distances, TextFormat::Tsv, DistanceMeasure::Jaccard);
+    assert_eq!(text.lines().next(), Some("file_1\tfile_2\tdistance"));
+    assert_eq!(text.lines().nth(1), Some("b.fna$c.fna\ta.fna\t0.250000"));
+}
+
+#[test]
+fn only_hamming_distances_are_imported() {
+    let distances = test_distances();
+    let text = exported(&distances, TextFormat::Phylip, DistanceMeasure::Jaccard);
+    let result = PairwiseDistances::read_text(
+        text.as_bytes(),
+        TextFormat::Phylip,
+        DistanceMeasure::Hamming,
+        distances.file2taxid.clone(),
+        None,
+        Some(14),
+        true,
+    );
+    assert!(result.unwrap_err().contains("not a hamming distance"));
+}
+
+#[test]
+fn missing_distances_are_refused() {
+    let distances = test_distances();
+    let text = exported(&distances, TextFormat::Tsv, DistanceMeasure::Hamming);
+    let truncated = text.lines().take(3).collect::<Vec<&str>>().join("\n");
+    let result = PairwiseDistances::read_text(
+        truncated.as_bytes(),
+        TextFormat::Tsv,
+        DistanceMeasure::Hamming,
+        distances.file2taxid.clone(),
+        None,
+        Some(14),
+        true,
+    );
+    assert!(result.unwrap_err().contains("there is no distance between"));
+}
+
+#[test]
+fn normalized_round_trip() {
+    let distances = test_distances();
+    let mut counts = vec![];
+    distances.write_kmer_counts(&mut counts).unwrap();
+    let kmer_counts =
+        PairwiseDistances::read_kmer_counts(counts.as_slice(), &distances.file2taxid).unwrap();
+    assert_eq!(Some(&kmer_counts), distances.kmer_counts.as_ref());
+
+    for measure in [DistanceMeasure::Jaccard, DistanceMeasure::Mash] {
+        for format in [TextFormat::Phylip, TextFormat::Tsv] {
+            let text = exported(&distances, format, measure);
+            let imported = PairwiseDistances::read_text(
+                text.as_bytes(),
+                format,
+                measure,
+                distances.file2taxid.clone(),
+                Some(kmer_counts.clone()),
+                Some(14),
+                true,
+            )
+            .unwrap();
+            assert_eq!(imported.distances, distances.distances);
+            assert_eq!(imported.kmer_counts, distances.kmer_counts);
+        }
+    }
+}
+
+#[test]
+fn normalized_distances_need_kmer_counts() {
+    let distances = test_distances();
+    let text = exported(&distances, TextFormat::Tsv, DistanceMeasure::Jaccard);
+    let result = PairwiseDistances::read_text(
+        text.as_bytes(),
+        TextFormat::Tsv,
+        DistanceMeasure::Jaccard,
+        distances.file2taxid.clone(),
+        None,
+        Some(14),
+        true,
+    );
+    assert!(result.unwrap_err().contains("needs the number of k-mers"));
+
+    let missing_count = "a.fna\t100\nd.fna\t120\n";
+    let result =
+        PairwiseDistances::read_kmer_counts(missing_count.as_bytes(), &distances.file2taxid);
+    assert!(result.unwrap_err().contains("b.fna$c.fna"));
+}
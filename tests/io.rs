@@ -0,0 +1,113 @@
+use musk::distances::PairwiseDistances;
+use musk::io::{
+    create_separate_output_file, dump_data_to_file, try_load_data_from_file, FileError, FileKind,
+};
+use std::fs::{self, File};
+use std::path::PathBuf;
+
+fn test_distances() -> PairwiseDistances {
+    PairwiseDistances {
+        kmer_len: Some(14),
+        canonical: true,
+        distances: vec![vec![], vec![3], vec![5, 7]],
+        file2taxid: vec![
+            ("a.fna".to_string(), 1),
+            ("b.fna".to_string(), 2),
+            ("c.fna".to_string(), 3),
+        ],
+        kmer_counts: Some(vec![10, 11, 12]),
+    }
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("musk_io_test_{}_{}", std::process::id(), name))
+}
+
+#[test]
+fn round_trip() {
+    let path = temp_path("round_trip.pd");
+    let distances = test_distances();
+    dump_data_to_file(
+        &distances,
+        FileKind::PairwiseDistances,
+        File::create(&path).unwrap(),
+    )
+    .unwrap();
+
+    let loaded = try_load_data_from_file::<PairwiseDistances>(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(distances, loaded);
+}
+
+#[test]
+fn truncated_file_fails_checksum() {
+    let path = temp_path("truncated.pd");
+    dump_data_to_file(
+        &test_distances(),
+        FileKind::PairwiseDistances,
+        File::create(&path).unwrap(),
+    )
+    .unwrap();
+
+    let bytes = fs::read(&path).unwrap();
+    fs::write(&path, &bytes[..bytes.len() - 4]).unwrap();
+
+    let result = try_load_data_from_file::<PairwiseDistances>(&path);
+    fs::remove_file(&path).unwrap();
+
+    assert!(matches!(result, Err(FileError::ChecksumMismatch)));
+}
+
+#[test]
+fn headerless_file_is_loaded() {
+    let path = temp_path("headerless.pd");
+    let distances = test_distances();
+    // Files without a header only stored the distances and file2taxid
+    bincode::serialize_into(
+        File::create(&path).unwrap(),
+        &(&distances.distances, &distances.file2taxid),
+    )
+    .unwrap();
+
+    let loaded = try_load_data_from_file::<PairwiseDistances>(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(
+        PairwiseDistances {
+            kmer_len: None,
+            kmer_counts: None,
+            ..distances
+        },
+        loaded
+    );
+}
+
+#[test]
+#[should_panic(expected = "non-canonical")]
+fn mixed_settings_are_refused() {
+    test_distances().check_settings(14, false);
+}
+
+#[test]
+fn input_is_not_overwritten() {
+    let directory = temp_path("separate_output");
+    fs::create_dir_all(&directory).unwrap();
+    let input_path = directory.join("musk.db");
+    fs::write(&input_path, [1_u8, 2, 3]).unwrap();
+
+    // The default output location in the directory of the input is refused
+    let result = std::panic::catch_unwind(|| {
+        create_separate_output_file(&directory, "musk.db", &[input_path.as_path()])
+    });
+    assert!(result.is_err());
+    assert_eq!(fs::read(&input_path).unwrap(), vec![1, 2, 3]);
+
+    create_separate_output_file(
+        &directory.join("updated"),
+        "musk.db",
+        &[input_path.as_path()],
+    );
+    assert!(directory.join("updated.musk.db").exists());
+    fs::remove_dir_all(&directory).unwrap();
+}
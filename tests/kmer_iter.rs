@@ -1,5 +1,5 @@
 use itertools::Itertools;
-use musk::kmer_iter::KmerIter;
+use musk::kmer_iter::{KmerIter, SpacedKmerIter, SpacedSeed};
 
 #[test]
 fn non_canonical() {
@@ -50,3 +50,40 @@ fn canonical() {
         KmerIter::from(sequence.as_bytes(), 14, true).collect_vec()
     );
 }
+
+#[test]
+fn spaced_seed() {
+    let sequence = "ACGTTGCA";
+    let spaced_seed = "101".parse::<SpacedSeed>().unwrap();
+    assert_eq!((spaced_seed.span(), spaced_seed.weight()), (3, 2));
+
+    // AG, CT, GT, TG, TC, GA
+    assert_eq!(
+        vec![0b_00_10, 0b_01_11, 0b_10_11, 0b_11_10, 0b_11_01, 0b_10_00],
+        SpacedKmerIter::from(sequence.as_bytes(), &spaced_seed, false).collect_vec()
+    );
+    // The reverse complements are CT, AG, AC, CA, GA, TC
+    assert_eq!(
+        vec![0b_00_10, 0b_00_10, 0b_00_01, 0b_01_00, 0b_10_00, 0b_10_00],
+        SpacedKmerIter::from(sequence.as_bytes(), &spaced_seed, true).collect_vec()
+    );
+}
+
+#[test]
+fn contiguous_spaced_seed() {
+    let sequence = "CGATTAAAGATAGAAATACACGNTGCGAGCAATCAAATT";
+    let spaced_seed = "11111111111111".parse::<SpacedSeed>().unwrap();
+    for canonical in [false, true] {
+        assert_eq!(
+            KmerIter::from(sequence.as_bytes(), 14, canonical).collect_vec(),
+            SpacedKmerIter::from(sequence.as_bytes(), &spaced_seed, canonical).collect_vec()
+        );
+    }
+}
+
+#[test]
+fn invalid_spaced_seeds() {
+    for mask in ["", "0110", "1021", "11111111111111111111111111111111"] {
+        assert!(mask.parse::<SpacedSeed>().is_err());
+    }
+}
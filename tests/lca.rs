@@ -0,0 +1,28 @@
+mod common;
+
+use common::small_taxonomy;
+use musk::lca::lowest_common_ancestor;
+
+#[test]
+fn shared_ancestor() {
+    let taxonomy = small_taxonomy();
+    assert_eq!(lowest_common_ancestor(&taxonomy, [11, 12]), Some(10));
+    assert_eq!(lowest_common_ancestor(&taxonomy, [11, 12, 21]), Some(2));
+    assert_eq!(lowest_common_ancestor(&taxonomy, [12, 12]), Some(12));
+}
+
+#[test]
+fn ancestor_and_descendant() {
+    let taxonomy = small_taxonomy();
+    assert_eq!(lowest_common_ancestor(&taxonomy, [10, 11]), Some(10));
+    assert_eq!(lowest_common_ancestor(&taxonomy, [21, 2]), Some(2));
+    assert_eq!(lowest_common_ancestor(&taxonomy, [1, 12]), Some(1));
+}
+
+#[test]
+fn missing_tax_id() {
+    let taxonomy = small_taxonomy();
+    assert_eq!(lowest_common_ancestor(&taxonomy, [11, 99]), None);
+    assert_eq!(lowest_common_ancestor(&taxonomy, [99]), None);
+    assert_eq!(lowest_common_ancestor(&taxonomy, []), None);
+}
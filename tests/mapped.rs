@@ -0,0 +1,116 @@
+use musk::database::Database;
+use musk::io::{dump_data_to_file, FileError, FileKind};
+use musk::kmer_index::IndexKind;
+use musk::mapped::load_mapped_from_file;
+use musk::sampling::Sampling;
+use roaring::RoaringTreemap;
+use std::fs::{self, File};
+use std::path::PathBuf;
+
+const KMER_LEN: usize = 4;
+
+fn test_database() -> Database {
+    let bitmaps = vec![
+        RoaringTreemap::from_iter([1_u64, 5, 9, 200]),
+        RoaringTreemap::from_iter([5_u64, 9, 17]),
+        RoaringTreemap::from_iter([0_u64, 9, 255]),
+    ];
+    let files = vec![
+        "a.fna".to_string(),
+        "b.fna".to_string(),
+        "c.fna".to_string(),
+    ];
+    Database::from(
+        bitmaps,
+        true,
+        files,
+        vec![1, 2, 3],
+        KMER_LEN,
+        None,
+        Sampling::All,
+        IndexKind::Dense,
+    )
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("musk_mapped_test_{}_{}", std::process::id(), name))
+}
+
+// Returns the blocks of every kmer, sorted so that databases with different layouts can be compared
+fn sorted_blocks(database: &Database) -> Vec<Vec<u16>> {
+    let mut blocks = (0..database.num_kmers())
+        .map(|position| database.rle_blocks(position).to_vec())
+        .collect::<Vec<Vec<u16>>>();
+    blocks.sort();
+    blocks
+}
+
+#[test]
+fn mapped_round_trip() {
+    let path = temp_path("round_trip.mdb");
+    let database = test_database();
+    database.dump_mapped(File::create(&path).unwrap()).unwrap();
+
+    let mapped = Database::load(&path);
+
+    assert_eq!(database.num_kmers(), mapped.num_kmers());
+    assert_eq!(database.files(), mapped.files());
+    assert_eq!(database.tax_ids(), mapped.tax_ids());
+    assert_eq!(database.p_values(), mapped.p_values());
+    assert_eq!(sorted_blocks(&database), sorted_blocks(&mapped));
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn mapped_to_database() {
+    let mapped_path = temp_path("to_database.mdb");
+    let path = temp_path("to_database.db");
+    let database = test_database();
+    database
+        .dump_mapped(File::create(&mapped_path).unwrap())
+        .unwrap();
+
+    // Writing a mapped database as a regular database should give back the same database
+    let mapped = Database::load(&mapped_path);
+    dump_data_to_file(&mapped, FileKind::Database, File::create(&path).unwrap()).unwrap();
+    let loaded = Database::load(&path);
+
+    assert_eq!(database.num_kmers(), loaded.num_kmers());
+    assert_eq!(sorted_blocks(&database), sorted_blocks(&loaded));
+
+    fs::remove_file(&mapped_path).unwrap();
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn corrupt_offsets_are_refused() {
+    let path = temp_path("corrupt_offsets.mdb");
+    let database = test_database();
+    database.dump_mapped(File::create(&path).unwrap()).unwrap();
+    let bytes = fs::read(&path).unwrap();
+
+    // The offsets are the num_kmers + 1 integers right before the blocks at the end of the file
+    let num_kmers = database.num_kmers();
+    let num_blocks = (0..num_kmers)
+        .map(|position| database.rle_blocks(position).len())
+        .sum::<usize>();
+    let offsets_end = bytes.len() - num_blocks * 2;
+    let offsets_start = offsets_end - (num_kmers + 1) * 8;
+    let (_metadata, blocks) = load_mapped_from_file(&path).unwrap();
+    assert_eq!(blocks.len(), num_kmers);
+
+    // A first offset past zero, an offset past the next one, and a last offset short of the end
+    for (offset, value) in [(0, 1), (1, num_blocks as u64 + 1), (num_kmers, 0)] {
+        let mut corrupt = bytes.clone();
+        let position = offsets_start + offset * 8;
+        corrupt[position..position + 8].copy_from_slice(&value.to_le_bytes());
+        fs::write(&path, corrupt).unwrap();
+        assert!(matches!(
+            load_mapped_from_file(&path),
+            Err(FileError::ChecksumMismatch)
+        ));
+    }
+
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,242 @@
+use musk::distances::NeighborDistances;
+use musk::order::{
+    greedy_ordering, mst_ordering, multi_start_greedy_ordering, or_opt, ordering_statistics,
+    two_opt, FileDistances,
+};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+// Files on a line, where the distance between two files is how far apart they are
+fn line_distances(positions: &[u32]) -> Vec<Vec<u32>> {
+    (0..positions.len())
+        .map(|index_1| {
+            (0..=index_1)
+                .map(|index_2| positions[index_1].abs_diff(positions[index_2]))
+                .collect()
+        })
+        .collect()
+}
+
+// The `num_neighbors` nearest files on a line
+fn line_neighbors(positions: &[u32], num_neighbors: usize) -> NeighborDistances {
+    let nearest = (0..positions.len())
+        .map(|index_1| {
+            let mut nearest = (0..positions.len())
+                .filter(|index_2| *index_2 != index_1)
+                .map(|index_2| {
+                    (
+                        index_2 as u32,
+                        positions[index_1].abs_diff(positions[index_2]),
+                    )
+                })
+                .collect::<Vec<(u32, u32)>>();
+            nearest.sort_by_key(|(index_2, distance)| (*distance, *index_2));
+            nearest.truncate(num_neighbors);
+            nearest
+        })
+        .collect();
+    let file2taxid = (0..positions.len())
+        .map(|index| (format!("{}.fna", index), index))
+        .collect();
+    NeighborDistances::from_nearest(14, true, nearest, file2taxid)
+}
+
+// The `num_neighbors` nearest files on a line without two files at the same place, which are
+// within `num_neighbors` files on either side
+fn distinct_line_neighbors(positions: &[u32], num_neighbors: usize) -> NeighborDistances {
+    let mut by_position = (0..positions.len()).collect::<Vec<usize>>();
+    by_position.sort_by_key(|index| positions[*index]);
+    let mut nearest = vec![vec![]; positions.len()];
+    for (rank, index_1) in by_position.iter().enumerate() {
+        let window =
+            rank.saturating_sub(num_neighbors)..(rank + num_neighbors + 1).min(positions.len());
+        let mut file_nearest = by_position[window]
+            .iter()
+            .filter(|index_2| *index_2 != index_1)
+            .map(|index_2| {
+                (
+                    *index_2 as u32,
+                    positions[*index_1].abs_diff(positions[*index_2]),
+                )
+            })
+            .collect::<Vec<(u32, u32)>>();
+        file_nearest.sort_by_key(|(index_2, distance)| (*distance, *index_2));
+        file_nearest.truncate(num_neighbors);
+        nearest[*index_1] = file_nearest;
+    }
+    let file2taxid = (0..positions.len())
+        .map(|index| (format!("{}.fna", index), index))
+        .collect();
+    NeighborDistances::from_nearest(14, true, nearest, file2taxid)
+}
+
+fn shuffled_positions(seed: u64) -> Vec<u32> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut positions = (0..60)
+        .map(|_| rng.random_range(0..10_000))
+        .collect::<Vec<u32>>();
+    positions.shuffle(&mut rng);
+    positions
+}
+
+fn shortest_length(positions: &[u32]) -> u64 {
+    (positions.iter().max().unwrap() - positions.iter().min().unwrap()) as u64
+}
+
+fn assert_is_ordering(ordering: &[usize], num_files: usize) {
+    let mut sorted = ordering.to_vec();
+    sorted.sort();
+    assert_eq!(sorted, (0..num_files).collect::<Vec<usize>>());
+}
+
+#[test]
+fn local_search_finds_shortest_line_ordering() {
+    for seed in 0..5 {
+        let positions = shuffled_positions(seed);
+        let distances = line_distances(&positions);
+
+        // Start in the middle of the line so that greedy has to jump back
+        let middle = (0..positions.len())
+            .min_by_key(|index| positions[*index].abs_diff(5_000))
+            .unwrap();
+        let mut ordering = greedy_ordering(&distances, middle);
+        let greedy_length = ordering_statistics(&ordering, &distances).1;
+
+        let gain = two_opt(&mut ordering, &distances) + or_opt(&mut ordering, &distances);
+        let improved_length = ordering_statistics(&ordering, &distances).1;
+        assert_is_ordering(&ordering, positions.len());
+        assert_eq!(greedy_length - gain, improved_length);
+        assert_eq!(improved_length, shortest_length(&positions));
+    }
+}
+
+#[test]
+fn or_opt_moves_segments() {
+    let positions = shuffled_positions(42);
+    let distances = line_distances(&positions);
+    let mut ordering = (0..positions.len()).collect::<Vec<usize>>();
+    let length = ordering_statistics(&ordering, &distances).1;
+
+    let gain = or_opt(&mut ordering, &distances);
+    assert_is_ordering(&ordering, positions.len());
+    assert!(gain > 0);
+    assert_eq!(ordering_statistics(&ordering, &distances).1, length - gain);
+}
+
+#[test]
+fn mst_ordering_from_end_of_line() {
+    let positions = shuffled_positions(7);
+    let distances = line_distances(&positions);
+    let leftmost = (0..positions.len())
+        .min_by_key(|index| positions[*index])
+        .unwrap();
+
+    // The minimum spanning tree of a line is the line itself
+    let ordering = mst_ordering(&distances, leftmost);
+    assert_is_ordering(&ordering, positions.len());
+    assert_eq!(
+        ordering_statistics(&ordering, &distances).1,
+        shortest_length(&positions)
+    );
+}
+
+#[test]
+fn multi_start_greedy_is_no_longer_than_greedy() {
+    let positions = shuffled_positions(3);
+    let distances = line_distances(&positions);
+    let num_starts = 8;
+
+    let ordering = multi_start_greedy_ordering(&distances, num_starts);
+    assert_is_ordering(&ordering, positions.len());
+    let length = ordering_statistics(&ordering, &distances).1;
+    for start in (0..num_starts).map(|start| start * positions.len() / num_starts) {
+        let greedy = greedy_ordering(&distances, start);
+        assert!(length <= ordering_statistics(&greedy, &distances).1);
+    }
+}
+
+#[test]
+fn nearest_neighbors_are_symmetric() {
+    let positions = shuffled_positions(9);
+    let neighbors = line_neighbors(&positions, 3);
+    for index_1 in 0..positions.len() {
+        assert!(neighbors.neighbors[index_1].len() >= 3);
+        for index_2 in 0..positions.len() {
+            assert_eq!(
+                neighbors.distance(index_1, index_2),
+                neighbors.distance(index_2, index_1)
+            );
+        }
+    }
+    // Files that are not neighbors are as far apart as the farthest neighbors
+    let farthest = positions.iter().max().unwrap() - positions.iter().min().unwrap();
+    assert!(neighbors.far_distance < farthest);
+}
+
+#[test]
+fn sparse_orderings_from_end_of_line() {
+    let positions = shuffled_positions(11);
+    let neighbors = line_neighbors(&positions, 6);
+    let leftmost = (0..positions.len())
+        .min_by_key(|index| positions[*index])
+        .unwrap();
+
+    for ordering in [
+        greedy_ordering(&neighbors, leftmost),
+        mst_ordering(&neighbors, leftmost),
+    ] {
+        assert_is_ordering(&ordering, positions.len());
+        assert_eq!(
+            ordering_statistics(&ordering, &line_distances(&positions)).1,
+            shortest_length(&positions)
+        );
+    }
+}
+
+#[test]
+fn sparse_orderings_jump_between_clusters() {
+    // Two clusters whose files only have neighbors in their own cluster
+    let positions = [0, 3, 1, 10_000, 4, 10_002, 2, 10_001];
+    let neighbors = line_neighbors(&positions, 2);
+    let in_first_cluster = |index: &usize| positions[*index] < 10;
+
+    for ordering in [
+        greedy_ordering(&neighbors, 3),
+        mst_ordering(&neighbors, 3),
+        multi_start_greedy_ordering(&neighbors, 4),
+    ] {
+        assert_is_ordering(&ordering, positions.len());
+        // Each cluster is visited in one go
+        let jumps = ordering
+            .windows(2)
+            .filter(|pair| in_first_cluster(&pair[0]) != in_first_cluster(&pair[1]))
+            .count();
+        assert_eq!(jumps, 1);
+    }
+}
+
+#[test]
+fn sparse_local_search_on_many_files() {
+    let mut rng = StdRng::seed_from_u64(13);
+    let mut positions = (0..20_000).collect::<Vec<u32>>();
+    positions.shuffle(&mut rng);
+    let neighbors = distinct_line_neighbors(&positions, 8);
+
+    // Start in the middle of the line so that greedy has to jump back
+    let middle = positions
+        .iter()
+        .position(|position| *position == 10_000)
+        .unwrap();
+    let mut ordering = greedy_ordering(&neighbors, middle);
+    let greedy_length = ordering_statistics(&ordering, &neighbors).1;
+
+    let gain = two_opt(&mut ordering, &neighbors) + or_opt(&mut ordering, &neighbors);
+    assert_is_ordering(&ordering, positions.len());
+    assert_eq!(
+        ordering_statistics(&ordering, &neighbors).1,
+        greedy_length - gain
+    );
+    assert_eq!(
+        ordering_statistics(&ordering, &neighbors).1,
+        shortest_length(&positions)
+    );
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use common::small_taxonomy;
+use musk::report::write_kreport;
+use std::collections::HashMap;
+
+fn kreport(tax_id_counts: &[(usize, u64)], unclassified: u64, zero_count: &[usize]) -> String {
+    let mut report = vec![];
+    write_kreport(
+        &mut report,
+        &small_taxonomy(),
+        &tax_id_counts
+            .iter()
+            .copied()
+            .collect::<HashMap<usize, u64>>(),
+        unclassified,
+        zero_count,
+    )
+    .unwrap();
+    String::from_utf8(report).unwrap()
+}
+
+#[test]
+fn clade_counts() {
+    let report = kreport(&[(10, 1), (11, 3), (21, 2)], 4, &[]);
+    let expected = [
+        " 40.00\t4\t4\tU\t0\tunclassified",
+        " 60.00\t6\t0\tR\t1\troot",
+        " 60.00\t6\t0\tD\t2\t  Bacteria",
+        " 40.00\t4\t1\tG\t10\t    Genus A",
+        " 30.00\t3\t3\tS\t11\t      Species A1",
+        " 20.00\t2\t0\tD1\t20\t    Group B",
+        " 20.00\t2\t2\tS\t21\t      Species B1",
+    ];
+    assert_eq!(report.lines().collect::<Vec<&str>>(), expected);
+}
+
+#[test]
+fn zero_count_tax_ids() {
+    let report = kreport(&[(11, 1)], 0, &[12]);
+    let expected = [
+        "  0.00\t0\t0\tU\t0\tunclassified",
+        "100.00\t1\t0\tR\t1\troot",
+        "100.00\t1\t0\tD\t2\t  Bacteria",
+        "100.00\t1\t0\tG\t10\t    Genus A",
+        "100.00\t1\t1\tS\t11\t      Species A1",
+        "  0.00\t0\t0\tS\t12\t      Species A2",
+    ];
+    assert_eq!(report.lines().collect::<Vec<&str>>(), expected);
+}
@@ -0,0 +1,71 @@
+use musk::sampling::Sampling;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+const KMER_LEN: usize = 21;
+
+const SAMPLINGS: [Sampling; 2] = [
+    Sampling::Minimizer { window: 10 },
+    Sampling::OpenSyncmer { smer_len: 15 },
+];
+
+fn random_sequence(rng: &mut StdRng, length: usize) -> Vec<u8> {
+    (0..length)
+        .map(|_| b"ACGT"[rng.random_range(0..4)])
+        .collect()
+}
+
+fn reverse_complement(sequence: &[u8]) -> Vec<u8> {
+    sequence
+        .iter()
+        .rev()
+        .map(|base| match base {
+            b'A' => b'T',
+            b'C' => b'G',
+            b'G' => b'C',
+            b'T' => b'A',
+            other => *other,
+        })
+        .collect()
+}
+
+fn sorted_kmers(sampling: Sampling, sequence: &[u8]) -> Vec<u64> {
+    let mut kmers = sampling
+        .kmers(sequence, KMER_LEN, None, true)
+        .collect::<Vec<u64>>();
+    kmers.sort();
+    kmers.dedup();
+    kmers
+}
+
+#[test]
+fn sampling_is_strand_independent() {
+    let mut sequence = random_sequence(&mut StdRng::seed_from_u64(42), 2_000);
+    sequence[700] = b'N';
+    for sampling in SAMPLINGS {
+        assert_eq!(
+            sorted_kmers(sampling, &sequence),
+            sorted_kmers(sampling, &reverse_complement(&sequence))
+        );
+    }
+}
+
+#[test]
+fn sampling_density() {
+    let sequence = random_sequence(&mut StdRng::seed_from_u64(42), 100_000);
+    let num_kmers = (sequence.len() - KMER_LEN + 1) as f64;
+    for sampling in SAMPLINGS {
+        let fraction = sampling.kmers(&sequence, KMER_LEN, None, true).count() as f64 / num_kmers;
+        let density = sampling.density(KMER_LEN);
+        assert!(
+            (fraction - density).abs() < density * 0.1,
+            "{} sampled {} of k-mers, expected {}",
+            sampling,
+            fraction,
+            density
+        );
+    }
+    assert_eq!(
+        Sampling::All.kmers(&sequence, KMER_LEN, None, true).count() as f64,
+        num_kmers
+    );
+}
@@ -0,0 +1,124 @@
+use musk::database::Database;
+use musk::kmer_index::IndexKind;
+use musk::sampling::Sampling;
+use musk::size_estimate::KmerSample;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use roaring::RoaringTreemap;
+
+const KMER_LEN: usize = 8;
+
+// Files share most of their kmers with the previous file, like an ordered file2taxid
+fn random_bitmaps() -> Vec<RoaringTreemap> {
+    let mut rng = StdRng::seed_from_u64(11);
+    let mut kmers = (0..2_000)
+        .map(|_| rng.random_range(0..4_u64.pow(KMER_LEN as u32)))
+        .collect::<Vec<u64>>();
+    (0..40)
+        .map(|_| {
+            for kmer in kmers.iter_mut() {
+                if rng.random_bool(0.05) {
+                    *kmer = rng.random_range(0..4_u64.pow(KMER_LEN as u32));
+                }
+            }
+            kmers.iter().copied().collect()
+        })
+        .collect()
+}
+
+fn file2taxid(num_files: usize) -> Vec<(String, usize)> {
+    (0..num_files).map(|i| (format!("{}.fna", i), i)).collect()
+}
+
+fn database_blocks(bitmaps: Vec<RoaringTreemap>) -> (usize, usize) {
+    let num_files = bitmaps.len();
+    let (files, tax_ids) = file2taxid(num_files).into_iter().unzip();
+    let database = Database::from(
+        bitmaps,
+        true,
+        files,
+        tax_ids,
+        KMER_LEN,
+        None,
+        Sampling::All,
+        IndexKind::HashMap,
+    );
+    let blocks = (0..database.num_kmers())
+        .map(|position| database.rle_blocks(position).len())
+        .sum();
+    (database.num_kmers(), blocks)
+}
+
+// The lower triangle of the hamming distances between the bitmaps
+fn distances(bitmaps: &[RoaringTreemap]) -> Vec<Vec<u32>> {
+    (0..bitmaps.len())
+        .map(|index_1| {
+            (0..=index_1)
+                .map(|index_2| {
+                    let intersection_size = bitmaps[index_1].intersection_len(&bitmaps[index_2]);
+                    (bitmaps[index_1].len() + bitmaps[index_2].len() - 2 * intersection_size) as u32
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn full_sample_counts_database_blocks() {
+    let bitmaps = random_bitmaps();
+    let kmer_sample =
+        KmerSample::from_bitmaps(&bitmaps, 1.0, KMER_LEN, true, file2taxid(bitmaps.len()));
+
+    // A reversed subset of the files is the same as a database built from those files
+    let ordering = (0..bitmaps.len()).rev().step_by(3).collect::<Vec<usize>>();
+    let ordered_bitmaps = ordering
+        .iter()
+        .map(|index| bitmaps[*index].clone())
+        .collect::<Vec<RoaringTreemap>>();
+
+    let estimate = kmer_sample.estimate_size(&ordering);
+    let (kmers, blocks) = database_blocks(ordered_bitmaps);
+    assert_eq!(estimate.kmers, kmers as f64);
+    assert_eq!(estimate.blocks, blocks as f64);
+}
+
+#[test]
+fn block_search_does_not_add_blocks() {
+    let bitmaps = random_bitmaps();
+    let distances = distances(&bitmaps);
+    let kmer_sample =
+        KmerSample::from_bitmaps(&bitmaps, 0.1, KMER_LEN, true, file2taxid(bitmaps.len()));
+
+    let mut ordering = (0..bitmaps.len()).collect::<Vec<usize>>();
+    ordering.shuffle(&mut StdRng::seed_from_u64(5));
+    let blocks = kmer_sample.estimate_size(&ordering).blocks;
+
+    let saved = kmer_sample.block_local_search(&mut ordering, &distances, 4);
+    let mut sorted = ordering.clone();
+    sorted.sort();
+    assert_eq!(sorted, (0..bitmaps.len()).collect::<Vec<usize>>());
+    assert!(saved > 0.0);
+    assert_eq!(kmer_sample.estimate_size(&ordering).blocks, blocks - saved);
+}
+
+#[test]
+fn block_search_keeps_subset() {
+    let bitmaps = random_bitmaps();
+    let distances = distances(&bitmaps);
+    let kmer_sample =
+        KmerSample::from_bitmaps(&bitmaps, 0.1, KMER_LEN, true, file2taxid(bitmaps.len()));
+
+    // The closest files of most files are left out of the ordering
+    let mut ordering = (0..bitmaps.len()).step_by(2).collect::<Vec<usize>>();
+    ordering.shuffle(&mut StdRng::seed_from_u64(5));
+    let blocks = kmer_sample.estimate_size(&ordering).blocks;
+
+    let saved = kmer_sample.block_local_search(&mut ordering, &distances, 4);
+    let mut sorted = ordering.clone();
+    sorted.sort();
+    assert_eq!(
+        sorted,
+        (0..bitmaps.len()).step_by(2).collect::<Vec<usize>>()
+    );
+    assert!(saved > 0.0);
+    assert_eq!(kmer_sample.estimate_size(&ordering).blocks, blocks - saved);
+}
@@ -0,0 +1,107 @@
+use musk::sketch::{Sketch, SketchIndex};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use roaring::RoaringTreemap;
+
+fn hamming_distance(bitmap_1: &RoaringTreemap, bitmap_2: &RoaringTreemap) -> u32 {
+    (bitmap_1.len() + bitmap_2.len() - 2 * bitmap_1.intersection_len(bitmap_2)) as u32
+}
+
+// Two bitmaps that share about `shared` of their kmers
+fn overlapping_bitmaps(num_kmers: usize, shared: f64) -> (RoaringTreemap, RoaringTreemap) {
+    let mut rng = StdRng::seed_from_u64(3);
+    let bitmap_1 = (0..num_kmers)
+        .map(|_| rng.random::<u64>() >> 8)
+        .collect::<RoaringTreemap>();
+    let bitmap_2 = bitmap_1
+        .iter()
+        .map(|kmer| {
+            if rng.random_bool(shared) {
+                kmer
+            } else {
+                rng.random::<u64>() >> 8
+            }
+        })
+        .collect::<RoaringTreemap>();
+    (bitmap_1, bitmap_2)
+}
+
+#[test]
+fn small_files_are_exact() {
+    let (bitmap_1, bitmap_2) = overlapping_bitmaps(500, 0.7);
+    let sketch_1 = Sketch::from_bitmap(&bitmap_1, 1_000);
+    let sketch_2 = Sketch::from_bitmap(&bitmap_2, 1_000);
+    assert_eq!(sketch_1.num_kmers(), bitmap_1.len());
+    assert_eq!(
+        sketch_1.hamming_distance(&sketch_2),
+        hamming_distance(&bitmap_1, &bitmap_2)
+    );
+    assert_eq!(sketch_1.hamming_distance(&sketch_1), 0);
+}
+
+#[test]
+fn large_files_are_estimated() {
+    for shared in [0.1, 0.5, 0.9] {
+        let (bitmap_1, bitmap_2) = overlapping_bitmaps(200_000, shared);
+        let sketch_1 = Sketch::from_bitmap(&bitmap_1, 4_000);
+        let sketch_2 = Sketch::from_bitmap(&bitmap_2, 4_000);
+
+        let exact = hamming_distance(&bitmap_1, &bitmap_2) as f64;
+        let estimate = sketch_1.hamming_distance(&sketch_2) as f64;
+        assert!(
+            (estimate - exact).abs() / exact < 0.05,
+            "estimated {} for a distance of {}",
+            estimate,
+            exact
+        );
+    }
+}
+
+#[test]
+fn empty_sketches() {
+    let empty = Sketch::from_bitmap(&RoaringTreemap::new(), 100);
+    let bitmap = (0..50_u64).collect::<RoaringTreemap>();
+    let sketch = Sketch::from_bitmap(&bitmap, 100);
+    assert_eq!(empty.hamming_distance(&empty), 0);
+    assert_eq!(empty.hamming_distance(&sketch), 50);
+}
+
+#[test]
+fn nearest_sketches_match_every_pair() {
+    // Groups of related bitmaps, along with empty and tiny bitmaps that share nothing
+    let mut rng = StdRng::seed_from_u64(9);
+    let mut bitmaps = vec![RoaringTreemap::new(), RoaringTreemap::new()];
+    bitmaps.extend((0..3_u64).map(|kmer| (kmer..kmer + 1).collect::<RoaringTreemap>()));
+    for _ in 0..5 {
+        let (bitmap_1, bitmap_2) = overlapping_bitmaps(2_000 + rng.random_range(0..2_000), 0.8);
+        let bitmap_3 = bitmap_2
+            .iter()
+            .filter(|_| rng.random_bool(0.7))
+            .collect::<RoaringTreemap>();
+        bitmaps.extend([bitmap_1, bitmap_2, bitmap_3]);
+    }
+    let sketches = bitmaps
+        .iter()
+        .map(|bitmap| Sketch::from_bitmap(bitmap, 200))
+        .collect::<Vec<Sketch>>();
+
+    let sketch_index = SketchIndex::new(&sketches);
+    for num_neighbors in [1, 3, 8, sketches.len()] {
+        for index in 0..sketches.len() {
+            let mut every_pair = (0..sketches.len())
+                .filter(|other| *other != index)
+                .map(|other| {
+                    (
+                        sketches[index].hamming_distance(&sketches[other]),
+                        other as u32,
+                    )
+                })
+                .collect::<Vec<(u32, u32)>>();
+            every_pair.sort_unstable();
+            every_pair.truncate(num_neighbors);
+            assert_eq!(
+                sketch_index.nearest(&sketches, index, num_neighbors),
+                every_pair
+            );
+        }
+    }
+}